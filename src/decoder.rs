@@ -1,15 +1,18 @@
 use byteorder::{LittleEndian, ReadBytesExt};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 
-use std::io::{self, BufReader, Cursor, Read, Seek};
+use std::io::{self, BufReader, Cursor, Read, Seek, Write};
 
 use std::ops::Range;
 use thiserror::Error;
 
-use crate::extended::{self, get_alpha_predictor, read_alpha_chunk, WebPExtendedInfo};
+use crate::extended::{
+    self, get_alpha_plane_predictor, get_alpha_predictor, read_alpha_chunk, WebPExtendedInfo,
+};
 
 use super::lossless::LosslessDecoder;
-use super::vp8::Vp8Decoder;
+use super::vp8::{Vp8Buffers, Vp8Decoder, Vp8Header};
 
 /// Errors that can occur when attempting to decode a WebP image
 #[derive(Error, Debug)]
@@ -27,9 +30,9 @@ pub enum DecodingError {
     #[error("Invalid WebP signature: {0:x?}")]
     WebpSignatureInvalid([u8; 4]),
 
-    /// An expected chunk was missing
-    #[error("An expected chunk was missing")]
-    ChunkMissing,
+    /// An expected chunk was missing. Contains the fourcc of the chunk.
+    #[error("An expected chunk was missing: {0:?}")]
+    ChunkMissing([u8; 4]),
 
     /// Chunk Header was incorrect or invalid in its usage
     #[error("Invalid Chunk header: {0:?}")]
@@ -40,8 +43,8 @@ pub enum DecodingError {
     InfoBitsInvalid { name: &'static str, value: u32 },
 
     /// Alpha chunk doesn't match the frame's size
-    #[error("Alpha chunk size mismatch")]
-    AlphaChunkSizeMismatch,
+    #[error("Alpha chunk size mismatch: expected {expected} bytes, got {actual}")]
+    AlphaChunkSizeMismatch { expected: usize, actual: usize },
 
     /// Image is too large, either for the platform's pointer size or generally
     #[error("Image too large")]
@@ -51,6 +54,10 @@ pub enum DecodingError {
     #[error("Frame outside image")]
     FrameOutsideImage,
 
+    /// The ANMF header declares a frame larger than a VP8 or VP8L bitstream can encode
+    #[error("Frame size {width}x{height} exceeds the maximum of 16384x16384")]
+    FrameTooLarge { width: u32, height: u32 },
+
     /// Signature of 0x2f not found
     #[error("Invalid lossless signature: {0:x?}")]
     LosslessSignatureInvalid(u8),
@@ -117,6 +124,22 @@ pub enum DecodingError {
     /// Invalid chunk size
     #[error("Invalid chunk size")]
     InvalidChunkSize,
+
+    /// The embedded ICC profile could not be parsed or converted to sRGB
+    #[error("Invalid or unsupported ICC profile")]
+    IccProfileInvalid,
+
+    /// The file contains more chunks than the decoder was allowed to scan
+    #[error("Too many chunks, the limit is {0}")]
+    TooManyChunks(usize),
+
+    /// The VP8 data is not a keyframe. Interframes never appear in valid WebP files.
+    #[error("VP8 frame is not a keyframe")]
+    NonKeyframe,
+
+    /// Decoding the animation would exceed the pixel budget
+    #[error("Pixel budget of {0} pixels exceeded")]
+    PixelBudgetExceeded(u64),
 }
 
 /// All possible RIFF chunks in a WebP image file
@@ -171,9 +194,73 @@ impl WebPRiffChunk {
             Self::Unknown(fourcc) => fourcc,
         }
     }
+}
+
+/// The type of a RIFF chunk in a WebP file, as returned by [`WebPDecoder::chunks`].
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
+pub enum ChunkType {
+    /// The RIFF container around the whole file
+    RIFF,
+    /// The WEBP signature at the start of the RIFF payload
+    WEBP,
+    /// A lossy VP8 bitstream
+    VP8,
+    /// A lossless VP8L bitstream
+    VP8L,
+    /// The header of an extended file, with its feature flags and canvas size
+    VP8X,
+    /// The background color and loop count of an animation
+    ANIM,
+    /// A frame of an animation
+    ANMF,
+    /// The alpha channel of a lossy image
+    ALPH,
+    /// An ICC color profile
+    ICCP,
+    /// EXIF metadata
+    EXIF,
+    /// XMP metadata
+    XMP,
+    /// A chunk not defined by the WebP spec, with its fourcc
+    Unknown([u8; 4]),
+}
+
+impl From<WebPRiffChunk> for ChunkType {
+    fn from(chunk: WebPRiffChunk) -> Self {
+        match chunk {
+            WebPRiffChunk::RIFF => Self::RIFF,
+            WebPRiffChunk::WEBP => Self::WEBP,
+            WebPRiffChunk::VP8 => Self::VP8,
+            WebPRiffChunk::VP8L => Self::VP8L,
+            WebPRiffChunk::VP8X => Self::VP8X,
+            WebPRiffChunk::ANIM => Self::ANIM,
+            WebPRiffChunk::ANMF => Self::ANMF,
+            WebPRiffChunk::ALPH => Self::ALPH,
+            WebPRiffChunk::ICCP => Self::ICCP,
+            WebPRiffChunk::EXIF => Self::EXIF,
+            WebPRiffChunk::XMP => Self::XMP,
+            WebPRiffChunk::Unknown(fourcc) => Self::Unknown(fourcc),
+        }
+    }
+}
 
-    pub(crate) fn is_unknown(&self) -> bool {
-        matches!(self, Self::Unknown(_))
+impl From<ChunkType> for WebPRiffChunk {
+    fn from(chunk: ChunkType) -> Self {
+        match chunk {
+            ChunkType::RIFF => Self::RIFF,
+            ChunkType::WEBP => Self::WEBP,
+            ChunkType::VP8 => Self::VP8,
+            ChunkType::VP8L => Self::VP8L,
+            ChunkType::VP8X => Self::VP8X,
+            ChunkType::ANIM => Self::ANIM,
+            ChunkType::ANMF => Self::ANMF,
+            ChunkType::ALPH => Self::ALPH,
+            ChunkType::ICCP => Self::ICCP,
+            ChunkType::EXIF => Self::EXIF,
+            ChunkType::XMP => Self::XMP,
+            ChunkType::Unknown(fourcc) => Self::Unknown(fourcc),
+        }
     }
 }
 
@@ -189,29 +276,475 @@ enum ImageKind {
     Extended(WebPExtendedInfo),
 }
 
+/// The variant of a WebP file, as determined by [`quick_classify`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum WebPClass {
+    /// A simple file containing only a VP8 chunk
+    Lossy,
+    /// A simple file containing only a VP8L chunk
+    Lossless,
+    /// An extended file starting with a VP8X chunk
+    Extended,
+}
+
+/// The parts of the WebP format supported by this build of the decoder, as returned by
+/// [`capabilities`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Lossy images and frames that are VP8 keyframes
+    pub vp8_keyframes: bool,
+    /// VP8 interframes, which never appear in valid WebP files
+    pub vp8_interframes: bool,
+    /// Lossless images and frames
+    pub vp8l: bool,
+    /// Uncompressed alpha channels
+    pub alpha_uncompressed: bool,
+    /// Alpha channels compressed with the lossless format
+    pub alpha_lossless: bool,
+    /// Animated images
+    pub animation: bool,
+    /// Extracting the ICC profile
+    pub icc_profile: bool,
+    /// Extracting EXIF metadata
+    pub exif: bool,
+    /// Extracting XMP metadata
+    pub xmp: bool,
+    /// Converting images with an ICC profile to sRGB, which requires the `color` feature
+    pub color_management: bool,
+}
+
+/// The layout of a decoded pixel.
+///
+/// More formats, such as luma or 16-bit samples, may be added in the future.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PixelFormat {
+    /// 8-bit red, green and blue
+    Rgb8,
+    /// 8-bit red, green, blue and alpha
+    Rgba8,
+}
+
+impl PixelFormat {
+    /// Returns the number of bytes used to store one pixel.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Rgba8 => 4,
+        }
+    }
+}
+
+/// How an image has to be transformed for display, as given by the EXIF orientation tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    /// 1: The image is displayed as stored
+    NoTransforms,
+    /// 2: Flip horizontally
+    FlipHorizontal,
+    /// 3: Rotate by 180 degrees
+    Rotate180,
+    /// 4: Flip vertically
+    FlipVertical,
+    /// 5: Rotate by 90 degrees clockwise, then flip horizontally
+    Rotate90FlipH,
+    /// 6: Rotate by 90 degrees clockwise
+    Rotate90,
+    /// 7: Rotate by 270 degrees clockwise, then flip horizontally
+    Rotate270FlipH,
+    /// 8: Rotate by 270 degrees clockwise
+    Rotate270,
+}
+
+impl Orientation {
+    fn from_exif(value: u16) -> Option<Self> {
+        Some(match value {
+            1 => Self::NoTransforms,
+            2 => Self::FlipHorizontal,
+            3 => Self::Rotate180,
+            4 => Self::FlipVertical,
+            5 => Self::Rotate90FlipH,
+            6 => Self::Rotate90,
+            7 => Self::Rotate270FlipH,
+            8 => Self::Rotate270,
+            _ => return None,
+        })
+    }
+}
+
+/// The channel order and count of the pixels written by
+/// [`WebPDecoder::read_image_with_layout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelLayout {
+    /// 8-bit red, green, blue and alpha
+    Rgba8,
+    /// 8-bit blue, green, red and alpha
+    Bgra8,
+    /// 8-bit red, green and blue
+    Rgb8,
+    /// 8-bit blue, green and red
+    Bgr8,
+}
+
+impl PixelLayout {
+    /// Returns the number of bytes used to store one pixel.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelLayout::Rgb8 | PixelLayout::Bgr8 => 3,
+            PixelLayout::Rgba8 | PixelLayout::Bgra8 => 4,
+        }
+    }
+}
+
+/// A decoded image together with its dimensions and pixel format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawImage {
+    /// The width of the image in pixels
+    pub width: u32,
+    /// The height of the image in pixels
+    pub height: u32,
+    /// The layout of each pixel in `data`
+    pub format: PixelFormat,
+    /// The pixels, row by row from top to bottom without padding
+    pub data: Vec<u8>,
+}
+
+impl RawImage {
+    /// Returns the pixels as 8-bit RGBA, adding an opaque alpha channel if needed.
+    pub fn as_rgba(&self) -> Cow<'_, [u8]> {
+        match self.format {
+            PixelFormat::Rgba8 => Cow::Borrowed(&self.data),
+            PixelFormat::Rgb8 => Cow::Owned(
+                self.data
+                    .chunks_exact(3)
+                    .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Returns the pixels as 8-bit RGB, dropping the alpha channel if there is one.
+    pub fn as_rgb(&self) -> Cow<'_, [u8]> {
+        match self.format {
+            PixelFormat::Rgb8 => Cow::Borrowed(&self.data),
+            PixelFormat::Rgba8 => Cow::Owned(
+                self.data
+                    .chunks_exact(4)
+                    .flat_map(|rgba| [rgba[0], rgba[1], rgba[2]])
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// A decoded image together with all of its metadata, as returned by
+/// [`WebPDecoder::decode_with_metadata`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedImage {
+    /// The pixels, row by row from top to bottom without padding
+    pub pixels: Vec<u8>,
+    /// The width of the image in pixels
+    pub width: u32,
+    /// The height of the image in pixels
+    pub height: u32,
+    /// The layout of each pixel in `pixels`
+    pub format: PixelFormat,
+    /// The raw bytes of the ICC profile, if any
+    pub icc: Option<Vec<u8>>,
+    /// The raw bytes of the EXIF metadata, if any
+    pub exif: Option<Vec<u8>>,
+    /// The raw bytes of the XMP metadata, if any
+    pub xmp: Option<Vec<u8>>,
+}
+
+/// A decoded image borrowed from the decoder, see [`WebPDecoder::read_image_borrowed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageView<'a> {
+    /// The width of the image in pixels
+    pub width: u32,
+    /// The height of the image in pixels
+    pub height: u32,
+    /// The layout of each pixel in `data`
+    pub format: PixelFormat,
+    /// The pixels, row by row from top to bottom without padding
+    pub data: &'a [u8],
+}
+
+/// How the huffman codes of lossless images are represented while decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HuffmanStrategy {
+    /// Walk a binary tree one bit at a time. This uses the least memory.
+    #[default]
+    Compact,
+    /// Additionally build a 256 entry lookup table per code, which resolves most symbols with a
+    /// single lookup. This costs about 4 KiB per huffman code, and lossless images can contain
+    /// thousands of codes.
+    FastTable,
+}
+
+/// The color space of the decoded pixels, as returned by [`WebPDecoder::color_space`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// sRGB, which the WebP spec assumes for images without an ICC profile
+    Srgb,
+    /// The color space described by the embedded ICC profile
+    IccProfile,
+}
+
+/// The color space in which semi-transparent animation frames are blended onto the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendSpace {
+    /// Blend the stored sRGB values with straight alpha, as described by the WebP spec and done
+    /// by libwebp.
+    #[default]
+    Srgb,
+    /// Convert to linear light and premultiply before blending, then convert back to sRGB.
+    ///
+    /// This avoids the dark fringes that blending in sRGB produces around semi-transparent
+    /// edges, at the cost of several floating point power functions per blended pixel.
+    Linear,
+}
+
+/// A summary of the structure of a WebP file, as returned by
+/// [`WebPDecoder::structure_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StructureReport {
+    /// The width of the image in pixels, as returned by [`WebPDecoder::dimensions`]
+    pub width: u32,
+    /// The height of the image in pixels, as returned by [`WebPDecoder::dimensions`]
+    pub height: u32,
+    /// Whether the file is a simple lossy, simple lossless or extended file
+    pub class: WebPClass,
+    /// The known chunks of the file, ordered by offset. Only the first chunk of each kind is
+    /// included, so for animations these are the chunks of the first frame.
+    pub chunks: Vec<ChunkReport>,
+    /// The number of animation frames, or 1 for still images
+    pub num_frames: usize,
+    /// The loop count, for animations
+    pub loop_count: Option<LoopCount>,
+    /// The background color in RGBA order, for animations
+    pub background_color: Option<[u8; 4]>,
+    /// Deviations from the WebP spec that the decoder tolerates
+    pub warnings: Vec<String>,
+}
+
+/// The header fields of an animation frame, as returned by [`WebPDecoder::frame_info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// The x offset of the frame on the canvas in pixels, already doubled from the stored value
+    pub x: u32,
+    /// The y offset of the frame on the canvas in pixels, already doubled from the stored value
+    pub y: u32,
+    /// The width of the frame in pixels
+    pub width: u32,
+    /// The height of the frame in pixels
+    pub height: u32,
+    /// How long the frame is displayed, in milliseconds
+    pub duration_ms: u32,
+    /// Whether the frame is alpha blended onto the canvas instead of replacing it
+    pub use_alpha_blending: bool,
+    /// Whether the frame's rectangle is cleared to the background color before the next frame
+    pub dispose: bool,
+}
+
+/// The position of a chunk within a WebP file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ChunkReport {
+    /// The FourCC of the chunk, such as `VP8L` or `ANMF`
+    pub fourcc: String,
+    /// The offset of the chunk header from the start of the file
+    pub offset: u64,
+    /// The size of the chunk data, excluding the header and padding
+    pub size: u64,
+}
+
+/// Where the decoder takes the image dimensions of an extended file from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizePolicy {
+    /// Use the canvas size declared in the VP8X chunk, as required by the WebP spec.
+    #[default]
+    UseCanvasSize,
+    /// Ignore the VP8X canvas size. Still images use the size of their VP8 or VP8L bitstream,
+    /// and animations use the bounding box of all frame rectangles.
+    UseFrameSize,
+}
+
+/// How often an animation is played, as declared in its ANIM chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum LoopCount {
+    /// The animation repeats forever
+    Forever,
+    /// The animation is played the given number of times
+    Finite(u16),
+}
+
+/// Progress reported to the callback set with [`WebPDecoder::set_progress_callback`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// The stream position up to which the file has been consumed, and the end of the RIFF
+    /// container
+    Bytes {
+        /// The absolute position in the stream, including any data before the RIFF header, such
+        /// as the offset passed to [`WebPDecoder::new_at_offset`]
+        bytes_read: u64,
+        /// The absolute position of the end of the RIFF container in the stream, measured like
+        /// `bytes_read`
+        total_bytes: u64,
+    },
+    /// The animation frame with the given index within the current loop has been decoded
+    Frame {
+        /// The index of the frame that was decoded, counting from zero at the start of each loop
+        frame_index: usize,
+        /// The number of frames in one loop of the animation
+        num_frames: usize,
+    },
+}
+
+/// Where the image data of a WebP file is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageStorage {
+    /// A VP8 or VP8L chunk directly inside the RIFF container. Use
+    /// [`WebPDecoder::read_image`] to decode it.
+    TopLevel,
+    /// One or more ANMF chunks of an animation. Use [`WebPDecoder::read_frame`] to decode them.
+    InAnimationFrame,
+}
+
+/// The header fields of an ALPH chunk, describing how the alpha channel was encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlphaEncodingInfo {
+    /// The spatial prediction filter: 0 for none, 1 for horizontal, 2 for vertical and 3 for
+    /// gradient
+    pub filtering_method: u8,
+    /// 0 if the alpha values are stored uncompressed, 1 if they are compressed with VP8L
+    pub compression_method: u8,
+    /// 0 if the alpha values were not preprocessed, 1 if the encoder quantized them into a
+    /// small number of levels
+    pub preprocessing: u8,
+}
+
+/// A transform of a lossless image, as returned by [`WebPDecoder::lossless_transforms`].
+///
+/// The decoder undoes the transforms in reverse order after decoding the pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LosslessTransform {
+    /// Each pixel is stored as the difference to a prediction from its neighbors, with the
+    /// predictor chosen per block of `1 << size_bits` by `1 << size_bits` pixels
+    Predictor { size_bits: u8 },
+    /// The red and blue channels are decorrelated from the green channel, with the coefficients
+    /// chosen per block of `1 << size_bits` by `1 << size_bits` pixels
+    Color { size_bits: u8 },
+    /// The green channel is subtracted from the red and blue channels
+    SubtractGreen,
+    /// The pixels are indices into a palette of `table_size` colors
+    ColorIndexing { table_size: u16 },
+}
+
+/// A rectangle on the canvas of an animation, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameRect {
+    /// The x offset of the left edge
+    pub x: u32,
+    /// The y offset of the top edge
+    pub y: u32,
+    /// The width of the rectangle
+    pub width: u32,
+    /// The height of the rectangle
+    pub height: u32,
+}
+
+impl FrameRect {
+    /// Returns the smallest rectangle containing both `self` and `other`.
+    fn union(self, other: FrameRect) -> FrameRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        FrameRect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+#[derive(Default)]
 struct AnimationState {
     next_frame: usize,
+    /// The loop count declared in the ANIM chunk, zero meaning forever
+    loop_count: u16,
     loops_before_done: Option<u16>,
     next_frame_start: u64,
-    dispose_next_frame: bool,
+    /// The rectangle of the previous frame, if it is to be disposed to the background color
+    dispose_rect: Option<FrameRect>,
     canvas: Option<Vec<u8>>,
+    dirty_rect: FrameRect,
+    frame_cache: VecDeque<CachedFrame>,
+    frame_cache_size: usize,
+}
+
+/// A decoded animation frame before compositing.
+struct AnimationFrame {
+    rect: FrameRect,
+    duration: u32,
+    use_alpha_blending: bool,
+    dispose: bool,
+    /// RGBA if `has_alpha`, RGB otherwise
+    data: Vec<u8>,
+    has_alpha: bool,
+    /// The position of the next ANMF chunk
+    end: u64,
+}
+
+/// The header of an ANMF chunk, before its frame is decoded.
+struct AnmfHeader {
+    rect: FrameRect,
+    duration: u32,
+    use_alpha_blending: bool,
+    dispose: bool,
+    chunks: FrameChunks,
+    /// The position of the next ANMF chunk
+    end: u64,
 }
-impl Default for AnimationState {
-    fn default() -> Self {
-        Self {
-            next_frame: 0,
-            loops_before_done: None,
-            next_frame_start: 0,
-            dispose_next_frame: true,
-            canvas: None,
+
+impl AnmfHeader {
+    fn into_frame(self, data: Vec<u8>, has_alpha: bool) -> AnimationFrame {
+        AnimationFrame {
+            rect: self.rect,
+            duration: self.duration,
+            use_alpha_blending: self.use_alpha_blending,
+            dispose: self.dispose,
+            data,
+            has_alpha,
+            end: self.end,
         }
     }
 }
 
+/// A composited frame, along with the state needed to continue decoding after it.
+struct CachedFrame {
+    index: usize,
+    duration: u32,
+    /// The RGBA canvas after compositing the frame
+    canvas: Vec<u8>,
+    dispose_rect: Option<FrameRect>,
+    next_frame_start: u64,
+}
+
 /// WebP image format decoder.
 pub struct WebPDecoder<R> {
     r: R,
     memory_limit: usize,
+    /// The limit for ICC profiles, EXIF and XMP metadata, if it differs from `memory_limit`
+    metadata_limit: Option<usize>,
+    pixel_budget: u64,
+    decoded_pixels: u64,
+    riff_start: u64,
+    riff_end: u64,
 
     width: u32,
     height: u32,
@@ -221,14 +754,69 @@ pub struct WebPDecoder<R> {
 
     kind: ImageKind,
     is_lossy: bool,
+    uses_vp8l: bool,
+    blend_space: BlendSpace,
+    huffman_strategy: HuffmanStrategy,
+    image_buffer: Vec<u8>,
+    #[cfg(feature = "color")]
+    require_known_colorspace: bool,
 
     chunks: HashMap<WebPRiffChunk, Range<u64>>,
+    progress_callback: Option<Box<dyn FnMut(ProgressEvent) + Send>>,
+    /// Kept between the frames of an animation so that decoding them does not reallocate.
+    vp8_buffers: Vp8Buffers,
+}
+
+impl<'a> WebPDecoder<Cursor<&'a [u8]>> {
+    /// Create a new WebPDecoder for an image that is fully held in memory.
+    ///
+    /// Reads from a byte slice are cheap, so no additional buffering is needed.
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self, DecodingError> {
+        Self::new(Cursor::new(data))
+    }
 }
 
 impl<R: Read + Seek> WebPDecoder<R> {
     /// Create a new WebPDecoder from the reader `r`. The decoder performs many small reads, so the
     /// reader should be buffered.
     pub fn new(r: R) -> Result<WebPDecoder<R>, DecodingError> {
+        Self::new_with_max_scan_chunks(r, usize::MAX)
+    }
+
+    /// Create a new WebPDecoder from the reader `r`, giving up with
+    /// [`DecodingError::TooManyChunks`] if the file has more than `max_chunks` top-level chunks.
+    ///
+    /// All chunks of an extended file are located while the decoder is constructed. A malicious
+    /// file can contain millions of tiny chunks, so untrusted input should be opened with a limit.
+    /// Real images rarely have more than a handful of chunks, plus one per animation frame.
+    pub fn new_with_max_scan_chunks(
+        r: R,
+        max_chunks: usize,
+    ) -> Result<WebPDecoder<R>, DecodingError> {
+        Self::new_with_options(r, max_chunks, true)
+    }
+
+    /// Create a new WebPDecoder from the reader `r` that tolerates VP8X flags without the chunks
+    /// they announce.
+    ///
+    /// Such a flag is treated as if it was not set, instead of failing with
+    /// [`DecodingError::ChunkMissing`]: a missing ICCP, EXIF or XMP chunk means there is no such
+    /// metadata, an animation without ANMF chunks is decoded as a still image, and one without an
+    /// ANIM chunk loops forever on a transparent background. The alpha flag of a lossy image
+    /// without an ALPH chunk is ignored. This helps with ingesting real-world files that do not
+    /// follow the spec exactly.
+    ///
+    /// Strictness has to be chosen when the decoder is constructed, since that is when the chunks
+    /// are located and checked.
+    pub fn new_lenient(r: R) -> Result<WebPDecoder<R>, DecodingError> {
+        Self::new_with_options(r, usize::MAX, false)
+    }
+
+    fn new_with_options(
+        r: R,
+        max_chunks: usize,
+        strict: bool,
+    ) -> Result<WebPDecoder<R>, DecodingError> {
         let mut decoder = WebPDecoder {
             r,
             width: 0,
@@ -238,13 +826,38 @@ impl<R: Read + Seek> WebPDecoder<R> {
             chunks: HashMap::new(),
             animation: Default::default(),
             memory_limit: usize::MAX,
+            metadata_limit: None,
+            pixel_budget: u64::MAX,
+            decoded_pixels: 0,
+            riff_start: 0,
+            riff_end: 0,
             is_lossy: false,
+            uses_vp8l: false,
+            blend_space: BlendSpace::default(),
+            huffman_strategy: HuffmanStrategy::default(),
+            image_buffer: Vec::new(),
+            #[cfg(feature = "color")]
+            require_known_colorspace: false,
+            progress_callback: None,
+            vp8_buffers: Vp8Buffers::default(),
         };
-        decoder.read_data()?;
+        decoder.read_data(max_chunks, strict)?;
         Ok(decoder)
     }
 
-    fn read_data(&mut self) -> Result<(), DecodingError> {
+    /// Create a new WebPDecoder for an image that starts `offset` bytes into the reader `r`, such
+    /// as one embedded in another file format.
+    ///
+    /// The reader is positioned at `offset` before parsing. All chunks are located using absolute
+    /// stream positions, so no other adjustment is needed.
+    pub fn new_at_offset(mut r: R, offset: u64) -> Result<WebPDecoder<R>, DecodingError> {
+        r.seek(io::SeekFrom::Start(offset))?;
+        Self::new(r)
+    }
+
+    fn read_data(&mut self, max_chunks: usize, strict: bool) -> Result<(), DecodingError> {
+        let riff_start = self.r.stream_position()?;
+        self.riff_start = riff_start;
         let (WebPRiffChunk::RIFF, riff_size, _) = read_chunk_header(&mut self.r)? else {
             return Err(DecodingError::ChunkHeaderInvalid(*b"RIFF"));
         };
@@ -256,6 +869,8 @@ impl<R: Read + Seek> WebPDecoder<R> {
 
         let (chunk, chunk_size, chunk_size_rounded) = read_chunk_header(&mut self.r)?;
         let start = self.r.stream_position()?;
+        // The end of the last chunk, which may be past the declared RIFF size.
+        let mut data_end = start + u64::from(chunk_size_rounded);
 
         match chunk {
             WebPRiffChunk::VP8 => {
@@ -263,9 +878,7 @@ impl<R: Read + Seek> WebPDecoder<R> {
 
                 let keyframe = tag & 1 == 0;
                 if !keyframe {
-                    return Err(DecodingError::UnsupportedFeature(
-                        "Non-keyframe frames".to_owned(),
-                    ));
+                    return Err(DecodingError::NonKeyframe);
                 }
 
                 let mut tag = [0u8; 3];
@@ -279,6 +892,14 @@ impl<R: Read + Seek> WebPDecoder<R> {
 
                 self.width = (w & 0x3FFF) as u32;
                 self.height = (h & 0x3FFF) as u32;
+                // Unlike all other dimensions in a WebP file, VP8 does not store them minus one,
+                // so they can be zero.
+                if self.width == 0 || self.height == 0 {
+                    return Err(DecodingError::InfoBitsInvalid {
+                        name: "canvas_size",
+                        value: 0,
+                    });
+                }
                 self.chunks
                     .insert(WebPRiffChunk::VP8, start..start + chunk_size as u64);
                 self.kind = ImageKind::Lossy;
@@ -290,25 +911,32 @@ impl<R: Read + Seek> WebPDecoder<R> {
                     return Err(DecodingError::LosslessSignatureInvalid(signature));
                 }
 
+                // The header packs a 14-bit width minus one, a 14-bit height minus one, the
+                // alpha_is_used hint and a 3-bit version. All 32 bits are accounted for, so the
+                // version is the only field that can be invalid.
                 let header = self.r.read_u32::<LittleEndian>()?;
                 let version = header >> 29;
                 if version != 0 {
                     return Err(DecodingError::VersionNumberInvalid(version as u8));
                 }
 
-                self.width = (1 + header) & 0x3FFF;
-                self.height = (1 + (header >> 14)) & 0x3FFF;
+                self.width = (header & 0x3FFF) + 1;
+                self.height = ((header >> 14) & 0x3FFF) + 1;
                 self.chunks
                     .insert(WebPRiffChunk::VP8L, start..start + chunk_size as u64);
                 self.kind = ImageKind::Lossless;
+                self.uses_vp8l = true;
             }
             WebPRiffChunk::VP8X => {
                 let mut info = extended::read_extended_header(&mut self.r)?;
                 self.width = info.canvas_width;
                 self.height = info.canvas_height;
+                self.chunks
+                    .insert(WebPRiffChunk::VP8X, start..start + u64::from(chunk_size));
 
+                // The RIFF size counts from the WEBP signature, which is 12 bytes before `start`.
                 let mut position = start + u64::from(chunk_size_rounded);
-                let max_position = position + u64::from(riff_size.saturating_sub(12));
+                let max_position = (start + u64::from(riff_size)).saturating_sub(12);
                 self.r.seek(io::SeekFrom::Start(position))?;
 
                 // Resist denial of service attacks by using a BufReader. In most images there
@@ -318,36 +946,56 @@ impl<R: Read + Seek> WebPDecoder<R> {
                 // expensive.
                 let mut reader = BufReader::with_capacity(64 << 10, &mut self.r);
 
+                // The VP8X chunk itself counts towards the limit.
+                let mut num_chunks = 1;
                 while position < max_position {
                     match read_chunk_header(&mut reader) {
                         Ok((chunk, chunk_size, chunk_size_rounded)) => {
-                            if chunk.is_unknown() {
-                                break;
+                            num_chunks += 1;
+                            if num_chunks > max_chunks {
+                                return Err(DecodingError::TooManyChunks(max_chunks));
                             }
 
                             let range = position + 8..position + 8 + u64::from(chunk_size);
                             position += 8 + u64::from(chunk_size_rounded);
                             self.chunks.entry(chunk).or_insert(range);
 
-                            if let WebPRiffChunk::ANMF = chunk {
-                                self.num_frames += 1;
-
-                                // If the image is animated, the image data chunk will be inside the
-                                // ANMF chunks, so we must inspect them to determine whether the
-                                // image contains any lossy image data. VP8 chunks store lossy data
-                                // and the spec says that lossless images SHOULD NOT contain ALPH
-                                // chunks, so we treat both as indicators of lossy images.
-                                if !self.is_lossy {
-                                    let (subchunk, ..) = read_chunk_header(&mut reader)?;
-                                    if let WebPRiffChunk::VP8 | WebPRiffChunk::ALPH = subchunk {
-                                        self.is_lossy = true;
+                            let consumed = match chunk {
+                                WebPRiffChunk::ANMF => {
+                                    self.num_frames += 1;
+
+                                    // If the image is animated, the image data chunks will be
+                                    // inside the ANMF chunks, following the 16 byte frame header.
+                                    // We must inspect them to determine which decoders the frames
+                                    // require. Only the first subchunk is needed since an ALPH
+                                    // chunk is always followed by a VP8 chunk.
+                                    if chunk_size >= 24 {
+                                        reader.seek_relative(16)?;
+                                        let (subchunk, subchunk_size, _) =
+                                            read_chunk_header(&mut reader)?;
+                                        24 + inspect_image_chunk(
+                                            &mut reader,
+                                            subchunk,
+                                            subchunk_size,
+                                            &mut self.is_lossy,
+                                            &mut self.uses_vp8l,
+                                        )?
+                                    } else {
+                                        0
                                     }
-                                    reader.seek_relative(i64::from(chunk_size_rounded) - 8)?;
-                                    continue;
                                 }
-                            }
-
-                            reader.seek_relative(i64::from(chunk_size_rounded))?;
+                                WebPRiffChunk::VP8 | WebPRiffChunk::VP8L | WebPRiffChunk::ALPH => {
+                                    inspect_image_chunk(
+                                        &mut reader,
+                                        chunk,
+                                        chunk_size,
+                                        &mut self.is_lossy,
+                                        &mut self.uses_vp8l,
+                                    )?
+                                }
+                                _ => 0,
+                            };
+                            reader.seek_relative(i64::from(chunk_size_rounded) - consumed)?;
                         }
                         Err(DecodingError::IoError(e))
                             if e.kind() == io::ErrorKind::UnexpectedEof =>
@@ -357,19 +1005,41 @@ impl<R: Read + Seek> WebPDecoder<R> {
                         Err(e) => return Err(e),
                     }
                 }
-                self.is_lossy = self.is_lossy || self.chunks.contains_key(&WebPRiffChunk::VP8);
-
-                if info.animation
-                    && (!self.chunks.contains_key(&WebPRiffChunk::ANIM)
-                        || !self.chunks.contains_key(&WebPRiffChunk::ANMF))
-                    || info.icc_profile && !self.chunks.contains_key(&WebPRiffChunk::ICCP)
-                    || info.exif_metadata && !self.chunks.contains_key(&WebPRiffChunk::EXIF)
-                    || info.xmp_metadata && !self.chunks.contains_key(&WebPRiffChunk::XMP)
-                    || !info.animation
+                data_end = position;
+
+                if strict {
+                    for (required, chunk) in [
+                        (info.animation, WebPRiffChunk::ANIM),
+                        (info.animation, WebPRiffChunk::ANMF),
+                        (info.icc_profile, WebPRiffChunk::ICCP),
+                        (info.exif_metadata, WebPRiffChunk::EXIF),
+                        (info.xmp_metadata, WebPRiffChunk::XMP),
+                    ] {
+                        if required && !self.chunks.contains_key(&chunk) {
+                            return Err(DecodingError::ChunkMissing(chunk.to_fourcc()));
+                        }
+                    }
+                } else {
+                    info.animation &= self.chunks.contains_key(&WebPRiffChunk::ANMF);
+                    info.icc_profile &= self.chunks.contains_key(&WebPRiffChunk::ICCP);
+                    info.exif_metadata &= self.chunks.contains_key(&WebPRiffChunk::EXIF);
+                    info.xmp_metadata &= self.chunks.contains_key(&WebPRiffChunk::XMP);
+                    if !info.animation
                         && self.chunks.contains_key(&WebPRiffChunk::VP8)
-                            == self.chunks.contains_key(&WebPRiffChunk::VP8L)
-                {
-                    return Err(DecodingError::ChunkMissing);
+                        && !self.chunks.contains_key(&WebPRiffChunk::ALPH)
+                    {
+                        info.alpha = false;
+                    }
+                }
+                if !info.animation {
+                    match (
+                        self.chunks.contains_key(&WebPRiffChunk::VP8),
+                        self.chunks.contains_key(&WebPRiffChunk::VP8L),
+                    ) {
+                        (false, false) => return Err(DecodingError::ChunkMissing(*b"VP8 ")),
+                        (true, true) => return Err(DecodingError::ChunkHeaderInvalid(*b"VP8L")),
+                        _ => {}
+                    }
                 }
 
                 // Decode ANIM chunk.
@@ -377,39 +1047,42 @@ impl<R: Read + Seek> WebPDecoder<R> {
                     match self.read_chunk(WebPRiffChunk::ANIM, 6) {
                         Ok(Some(chunk)) => {
                             let mut cursor = Cursor::new(chunk);
-                            cursor.read_exact(&mut info.background_color)?;
-                            match cursor.read_u16::<LittleEndian>()? {
-                                0 => self.animation.loops_before_done = None,
-                                n => self.animation.loops_before_done = Some(n),
-                            }
-                            self.animation.next_frame_start =
-                                self.chunks.get(&WebPRiffChunk::ANMF).unwrap().start;
+                            // The ANIM chunk stores the color as BGRA, but the canvas is RGBA.
+                            let mut bgra = [0; 4];
+                            cursor.read_exact(&mut bgra)?;
+                            let [b, g, r, a] = bgra;
+                            info.background_color = [r, g, b, a];
+                            self.animation.loop_count = cursor.read_u16::<LittleEndian>()?;
+                            self.animation.loops_before_done = match self.animation.loop_count {
+                                0 => None,
+                                n => Some(n),
+                            };
                         }
-                        Ok(None) => return Err(DecodingError::ChunkMissing),
+                        Ok(None) if !strict => {}
+                        Ok(None) => return Err(DecodingError::ChunkMissing(*b"ANIM")),
                         Err(DecodingError::MemoryLimitExceeded) => {
                             return Err(DecodingError::InvalidChunkSize)
                         }
                         Err(e) => return Err(e),
                     }
+                    // The recorded range excludes the chunk header, but `read_frame` expects to
+                    // start at the header.
+                    self.animation.next_frame_start =
+                        self.chunks.get(&WebPRiffChunk::ANMF).unwrap().start - 8;
                 }
 
                 // If the image is animated, the image data chunk will be inside the ANMF chunks. We
                 // store the ALPH, VP8, and VP8L chunks (as applicable) of the first frame in the
                 // hashmap so that we can read them later.
                 if let Some(range) = self.chunks.get(&WebPRiffChunk::ANMF).cloned() {
-                    self.r.seek(io::SeekFrom::Start(range.start))?;
-                    let mut position = range.start;
-
-                    for _ in 0..2 {
-                        let (subchunk, subchunk_size, subchunk_size_rounded) =
-                            read_chunk_header(&mut self.r)?;
-                        let subrange = position + 8..position + 8 + u64::from(subchunk_size);
-                        self.chunks.entry(subchunk).or_insert(subrange.clone());
-
-                        position += 8 + u64::from(subchunk_size_rounded);
-                        if position + 8 > range.end {
-                            break;
-                        }
+                    let frame_chunks = find_frame_chunks(&mut self.r, range.start + 16..range.end)?;
+                    self.chunks
+                        .entry(frame_chunks.image)
+                        .or_insert(frame_chunks.image_range);
+                    if let Some(alpha_range) = frame_chunks.alpha_range {
+                        self.chunks
+                            .entry(WebPRiffChunk::ALPH)
+                            .or_insert(alpha_range);
                     }
                 }
 
@@ -418,9 +1091,23 @@ impl<R: Read + Seek> WebPDecoder<R> {
             _ => return Err(DecodingError::ChunkHeaderInvalid(chunk.to_fourcc())),
         };
 
+        let stream_len = self.r.seek(io::SeekFrom::End(0))?;
+        self.riff_end = (riff_start + 8 + u64::from(riff_size))
+            .max(data_end)
+            .min(stream_len);
+
         Ok(())
     }
 
+    /// Returns the stream position right after the RIFF container, where any data appended to the
+    /// file starts.
+    ///
+    /// If the chunks extend past the size declared in the RIFF header, the end of the last chunk
+    /// is used instead. The position never exceeds the length of the stream.
+    pub fn trailing_data_offset(&self) -> u64 {
+        self.riff_end
+    }
+
     /// Sets the maximum amount of memory that the decoder is allowed to allocate at once.
     ///
     /// TODO: Some allocations currently ignore this limit.
@@ -428,6 +1115,42 @@ impl<R: Read + Seek> WebPDecoder<R> {
         self.memory_limit = limit;
     }
 
+    /// Sets the maximum size of the ICC profile, EXIF and XMP metadata that the decoder reads.
+    ///
+    /// Larger metadata fails with [`DecodingError::MemoryLimitExceeded`]. This is independent of
+    /// [`set_memory_limit`](Self::set_memory_limit), so that large metadata can be allowed
+    /// without raising the limit for the image data, or the other way around. Until this is
+    /// called, the memory limit applies to metadata as well.
+    pub fn set_metadata_limit(&mut self, limit: usize) {
+        self.metadata_limit = Some(limit);
+    }
+
+    /// Sets a callback that is invoked with a [`ProgressEvent`] after each animation frame is
+    /// decoded.
+    ///
+    /// The chunks of the file are located while the decoder is constructed, so that part is not
+    /// reported. Each frame produces a [`ProgressEvent::Bytes`] event followed by a
+    /// [`ProgressEvent::Frame`] event. The callback has to be `Send` so that the decoder can still
+    /// be moved to another thread.
+    pub fn set_progress_callback(&mut self, f: Box<dyn FnMut(ProgressEvent) + Send>) {
+        self.progress_callback = Some(f);
+    }
+
+    fn report_progress(&mut self, event: ProgressEvent) {
+        if let Some(f) = self.progress_callback.as_mut() {
+            f(event);
+        }
+    }
+
+    /// Sets the maximum number of pixels that may be decoded across all frames of an animation.
+    ///
+    /// Every decoded frame counts with its full size, including frames decoded again on later
+    /// loops. A frame that would exceed the budget is not decoded and
+    /// [`DecodingError::PixelBudgetExceeded`] is returned instead.
+    pub fn set_pixel_budget(&mut self, total_pixels: u64) {
+        self.pixel_budget = total_pixels;
+    }
+
     /// Returns true if the image is animated.
     pub fn has_animation(&self) -> bool {
         match &self.kind {
@@ -436,8 +1159,37 @@ impl<R: Read + Seek> WebPDecoder<R> {
         }
     }
 
+    /// Returns true if the image is animated and has more than one frame.
+    ///
+    /// Unlike [`has_animation`](Self::has_animation), which reflects the animation flag of the
+    /// VP8X chunk, this treats an animation with a single frame as a still image. Viewers can then
+    /// decode such files with [`read_image`](Self::read_image) and ignore the frame duration and
+    /// loop count.
+    pub fn effective_is_animated(&self) -> bool {
+        self.has_animation() && self.num_frames > 1
+    }
+
+    /// Returns how often the animation is to be played, regardless of how many loops were
+    /// already decoded. Still images return `LoopCount::Finite(1)`.
+    pub fn loop_count(&self) -> LoopCount {
+        match self.animation.loop_count {
+            _ if !self.has_animation() => LoopCount::Finite(1),
+            0 => LoopCount::Forever,
+            n => LoopCount::Finite(n),
+        }
+    }
+
+    /// Returns whether the image data is stored at the top level or inside animation frames.
+    pub fn image_storage(&self) -> ImageStorage {
+        if self.has_animation() {
+            ImageStorage::InAnimationFrame
+        } else {
+            ImageStorage::TopLevel
+        }
+    }
+
     /// Returns whether the image has an alpha channel. If so, the pixel format is Rgba8 and
-    /// otherwise Rgb8.
+    /// otherwise Rgb8, see [`output_format`](Self::output_format).
     pub fn has_alpha(&self) -> bool {
         match &self.kind {
             ImageKind::Lossy => false,
@@ -446,12 +1198,49 @@ impl<R: Read + Seek> WebPDecoder<R> {
         }
     }
 
+    /// Returns the format of the pixels written by [`read_image`](Self::read_image) and
+    /// [`read_frame`](Self::read_frame).
+    ///
+    /// This is [`PixelFormat::Rgba8`] if the image has an alpha channel and [`PixelFormat::Rgb8`]
+    /// otherwise.
+    pub fn output_format(&self) -> PixelFormat {
+        if self.has_alpha() {
+            PixelFormat::Rgba8
+        } else {
+            PixelFormat::Rgb8
+        }
+    }
+
+    /// Returns the number of frames of an animation, or 1 for still images.
+    pub fn num_frames(&self) -> usize {
+        if self.has_animation() {
+            self.num_frames
+        } else {
+            1
+        }
+    }
+
     /// Returns whether the image is lossy. For animated images, this is true if any frame is lossy.
     pub fn is_lossy(&mut self) -> bool {
         self.is_lossy
     }
 
+    /// Returns whether decoding the image requires the lossy (VP8) decoder. For animated images,
+    /// this is true if any frame is lossy.
+    pub fn requires_vp8(&self) -> bool {
+        self.is_lossy
+    }
+
+    /// Returns whether decoding the image requires the lossless (VP8L) decoder. This includes
+    /// alpha channels that are compressed as VP8L bitstreams. For animated images, this is true if
+    /// any frame requires it.
+    pub fn requires_vp8l(&self) -> bool {
+        self.uses_vp8l
+    }
+
     /// Sets the background color if the image is an extended and animated webp.
+    ///
+    /// The color is given in RGBA order, unlike the BGRA order in which the ANIM chunk stores it.
     pub fn set_background_color(&mut self, color: [u8; 4]) -> Result<(), DecodingError> {
         if let ImageKind::Extended(info) = &mut self.kind {
             info.background_color = color;
@@ -463,11 +1252,215 @@ impl<R: Read + Seek> WebPDecoder<R> {
         }
     }
 
+    /// Returns the RGBA color that the canvas of an animation is cleared to, including any
+    /// override set with [`set_background_color`](Self::set_background_color). Still images
+    /// return `None`.
+    pub fn background_color(&self) -> Option<[u8; 4]> {
+        match &self.kind {
+            ImageKind::Extended(info) if info.animation => Some(info.background_color),
+            _ => None,
+        }
+    }
+
+    /// Sets whether [`read_image_srgb`](Self::read_image_srgb) fails with
+    /// [`DecodingError::IccProfileInvalid`] for images with an ICC profile that cannot be parsed.
+    ///
+    /// By default such profiles are ignored and the image is treated as sRGB, which suits general
+    /// use. Color critical workflows can enable this to reject images whose colors would otherwise
+    /// be silently misinterpreted.
+    #[cfg(feature = "color")]
+    pub fn set_require_known_colorspace(&mut self, require: bool) {
+        self.require_known_colorspace = require;
+    }
+
+    /// Sets the color space used to blend animation frames onto the canvas.
+    ///
+    /// Defaults to [`BlendSpace::Srgb`], which matches the WebP spec.
+    pub fn set_blend_space(&mut self, blend_space: BlendSpace) {
+        self.blend_space = blend_space;
+    }
+
+    /// Returns the type and data size of the chunks found in the file, ordered by their position.
+    ///
+    /// Only the first chunk of each type is listed. For animations, the image data chunks are
+    /// the ones of the first frame.
+    pub fn chunks(&self) -> impl Iterator<Item = (ChunkType, u64)> {
+        let mut chunks: Vec<(u64, ChunkType, u64)> = self
+            .chunks
+            .iter()
+            .map(|(&chunk, range)| (range.start, chunk.into(), range.end - range.start))
+            .collect();
+        chunks.sort_by_key(|&(start, _, _)| start);
+        chunks.into_iter().map(|(_, chunk, size)| (chunk, size))
+    }
+
+    /// Checks that the top-level chunks exactly fill the payload declared in the RIFF header.
+    ///
+    /// Decoding tolerates chunks that extend past the declared RIFF size or leave some of it
+    /// unused, so that slightly corrupt files still decode. This walks the chunk headers again
+    /// without decoding anything, and returns [`DecodingError::InvalidChunkSize`] if the chunks,
+    /// including their headers and padding, do not add up to the RIFF size or the stream ends
+    /// before the RIFF payload does. The subchunks of ANMF chunks are not checked.
+    pub fn verify_structure(&mut self) -> Result<(), DecodingError> {
+        self.r.seek(io::SeekFrom::Start(self.riff_start))?;
+        let (_, riff_size, _) = read_chunk_header(&mut self.r)?;
+        let riff_end = self.riff_start + 8 + u64::from(riff_size);
+        if riff_end > self.r.seek(io::SeekFrom::End(0))? {
+            return Err(DecodingError::InvalidChunkSize);
+        }
+
+        // The payload starts with the WEBP signature.
+        let mut position = self.riff_start + 12;
+        while position < riff_end {
+            if position + 8 > riff_end {
+                return Err(DecodingError::InvalidChunkSize);
+            }
+            self.r.seek(io::SeekFrom::Start(position))?;
+            let (_, _, size_rounded) = read_chunk_header(&mut self.r)?;
+            position += 8 + u64::from(size_rounded);
+        }
+
+        if position != riff_end {
+            return Err(DecodingError::InvalidChunkSize);
+        }
+        Ok(())
+    }
+
+    /// Returns a summary of the chunks and parsed header fields of the file, e.g. for attaching
+    /// to bug reports.
+    ///
+    /// With the `serde` feature, the report implements `serde::Serialize`.
+    pub fn structure_report(&self) -> StructureReport {
+        let mut chunks: Vec<_> = self
+            .chunks
+            .iter()
+            .map(|(chunk, range)| ChunkReport {
+                fourcc: String::from_utf8_lossy(&chunk.to_fourcc()).into_owned(),
+                offset: range.start - 8,
+                size: range.end - range.start,
+            })
+            .collect();
+        chunks.sort_by_key(|chunk| chunk.offset);
+
+        let mut warnings = Vec::new();
+        let class = match &self.kind {
+            ImageKind::Lossy => WebPClass::Lossy,
+            ImageKind::Lossless => WebPClass::Lossless,
+            ImageKind::Extended(info) => {
+                for (flag, chunk, name) in [
+                    (info.icc_profile, WebPRiffChunk::ICCP, "ICC profile"),
+                    (info.exif_metadata, WebPRiffChunk::EXIF, "EXIF"),
+                    (info.xmp_metadata, WebPRiffChunk::XMP, "XMP"),
+                ] {
+                    if !flag && self.chunks.contains_key(&chunk) {
+                        warnings.push(format!("{name} chunk present without its VP8X flag"));
+                    }
+                }
+                if !info.animation
+                    && self.chunks.contains_key(&WebPRiffChunk::VP8L)
+                    && self.chunks.contains_key(&WebPRiffChunk::ALPH)
+                {
+                    warnings.push("ALPH chunk in a lossless image".to_owned());
+                }
+                WebPClass::Extended
+            }
+        };
+
+        let animation = self.background_color();
+
+        StructureReport {
+            width: self.width,
+            height: self.height,
+            class,
+            chunks,
+            num_frames: self.num_frames(),
+            loop_count: animation.map(|_| self.loop_count()),
+            background_color: animation,
+            warnings,
+        }
+    }
+
+    /// Sets where the dimensions of extended files are taken from.
+    ///
+    /// This rescues files whose VP8X canvas size disagrees with their image data, which some
+    /// encoders produce. The new dimensions are returned by [`dimensions`](Self::dimensions)
+    /// right away, and animations restart from the first frame. Simple files always use the size
+    /// of their bitstream.
+    ///
+    /// Returns [`DecodingError::InfoBitsInvalid`] if the new dimensions are zero and
+    /// [`DecodingError::ImageTooLarge`] if the RGBA canvas they need would exceed the memory limit,
+    /// keeping the previous dimensions in both cases.
+    pub fn set_size_policy(&mut self, policy: SizePolicy) -> Result<(), DecodingError> {
+        let ImageKind::Extended(info) = &self.kind else {
+            return Ok(());
+        };
+
+        let (width, height) = match policy {
+            SizePolicy::UseCanvasSize => (info.canvas_width, info.canvas_height),
+            SizePolicy::UseFrameSize if info.animation => {
+                let (mut width, mut height) = (0, 0);
+                for offset in self.frame_offsets()? {
+                    self.r.seek(io::SeekFrom::Start(offset + 8))?;
+                    let frame = read_frame_info(&mut self.r)?;
+                    width = width.max(frame.x + frame.width);
+                    height = height.max(frame.y + frame.height);
+                }
+                (width, height)
+            }
+            SizePolicy::UseFrameSize => {
+                let (chunk, range) = match self.chunks.get(&WebPRiffChunk::VP8) {
+                    Some(range) => (WebPRiffChunk::VP8, range.clone()),
+                    None => (
+                        WebPRiffChunk::VP8L,
+                        self.chunks[&WebPRiffChunk::VP8L].clone(),
+                    ),
+                };
+                self.r.seek(io::SeekFrom::Start(range.start))?;
+                read_bitstream_dimensions(&mut self.r, chunk)?
+            }
+        };
+
+        // The VP8X canvas size was validated by the constructor, but these dimensions were not.
+        if width == 0 || height == 0 {
+            return Err(DecodingError::InfoBitsInvalid {
+                name: "canvas_size",
+                value: 0,
+            });
+        }
+        match width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(4))
+        {
+            Some(bytes) if bytes as usize <= self.memory_limit => {}
+            _ => return Err(DecodingError::ImageTooLarge),
+        }
+
+        self.width = width;
+        self.height = height;
+        self.animation.canvas = None;
+        self.animation.frame_cache.clear();
+        if self.has_animation() {
+            self.rewind_animation();
+        }
+        Ok(())
+    }
+
+    /// Sets how the huffman codes of lossless image data are represented while decoding.
+    ///
+    /// Defaults to [`HuffmanStrategy::Compact`].
+    pub fn set_huffman_strategy(&mut self, strategy: HuffmanStrategy) {
+        self.huffman_strategy = strategy;
+    }
+
     /// Returns the (width, height) of the image in pixels.
     pub fn dimensions(&self) -> (u32, u32) {
         (self.width, self.height)
     }
 
+    fn metadata_limit(&self) -> usize {
+        self.metadata_limit.unwrap_or(self.memory_limit)
+    }
+
     fn read_chunk(
         &mut self,
         chunk: WebPRiffChunk,
@@ -488,65 +1481,333 @@ impl<R: Read + Seek> WebPDecoder<R> {
         }
     }
 
+    /// Returns the color space of the image.
+    ///
+    /// Images without an ICC profile, or with an empty placeholder profile that some encoders
+    /// write, are sRGB. The profile itself is not parsed.
+    pub fn color_space(&self) -> ColorSpace {
+        match self.chunks.get(&WebPRiffChunk::ICCP) {
+            Some(range) if !range.is_empty() => ColorSpace::IccProfile,
+            _ => ColorSpace::Srgb,
+        }
+    }
+
+    /// Returns true if the image has an ICC profile, without reading it.
+    ///
+    /// Like [`icc_profile`](Self::icc_profile), an empty ICCP chunk does not count as a profile.
+    pub fn has_icc_profile(&self) -> bool {
+        self.color_space() == ColorSpace::IccProfile
+    }
+
     /// Returns the raw bytes of the ICC profile, or None if there is no ICC profile.
+    ///
+    /// An empty ICCP chunk is treated as if there was no ICC profile.
     pub fn icc_profile(&mut self) -> Result<Option<Vec<u8>>, DecodingError> {
-        self.read_chunk(WebPRiffChunk::ICCP, self.memory_limit)
+        if self.color_space() == ColorSpace::Srgb {
+            return Ok(None);
+        }
+        self.read_chunk(WebPRiffChunk::ICCP, self.metadata_limit())
     }
 
     /// Returns the raw bytes of the EXIF metadata, or None if there is no EXIF metadata.
     pub fn exif_metadata(&mut self) -> Result<Option<Vec<u8>>, DecodingError> {
-        self.read_chunk(WebPRiffChunk::EXIF, self.memory_limit)
+        self.read_chunk(WebPRiffChunk::EXIF, self.metadata_limit())
     }
 
-    // Returns the raw bytes of the XMP metadata, or None if there is no XMP metadata.
-    pub fn xmp_metadata(&mut self) -> Result<Option<Vec<u8>>, DecodingError> {
-        self.read_chunk(WebPRiffChunk::XMP, self.memory_limit)
+    /// Returns true if the image has EXIF metadata, without reading it.
+    pub fn has_exif(&self) -> bool {
+        self.chunks.contains_key(&WebPRiffChunk::EXIF)
+    }
+
+    /// Returns the orientation given by the EXIF metadata.
+    ///
+    /// Only the orientation tag of the first image directory is looked up. Returns `None` if
+    /// there is no EXIF metadata, if it has no valid orientation tag, or if it is malformed.
+    pub fn orientation(&mut self) -> Result<Option<Orientation>, DecodingError> {
+        Ok(self
+            .exif_metadata()?
+            .and_then(|exif| read_exif_orientation(&exif))
+            .and_then(Orientation::from_exif))
+    }
+
+    /// Returns the data of the first top-level chunk with the given fourcc, or None if there is
+    /// no such chunk.
+    ///
+    /// This is meant for chunks that are not defined by the WebP spec, like application specific
+    /// depth or gain maps, but works for any chunk type. Returns
+    /// [`DecodingError::MemoryLimitExceeded`] if the chunk is larger than the memory limit.
+    pub fn read_unknown_chunk(
+        &mut self,
+        fourcc: [u8; 4],
+    ) -> Result<Option<Vec<u8>>, DecodingError> {
+        self.read_chunk(WebPRiffChunk::from_fourcc(fourcc), self.memory_limit)
+    }
+
+    /// Returns the data of the first chunk of the given type exactly as stored, without its
+    /// header, or None if there is no such chunk.
+    ///
+    /// This lets tools that remux WebP files copy chunks verbatim, including ones they do not
+    /// understand. Unlike [`icc_profile`](Self::icc_profile), an empty ICCP chunk is returned as
+    /// is. For animations, the VP8, VP8L and ALPH chunks are the ones of the first frame. Returns
+    /// [`DecodingError::MemoryLimitExceeded`] if the chunk is larger than the metadata limit, see
+    /// [`set_metadata_limit`](Self::set_metadata_limit).
+    pub fn raw_chunk(&mut self, chunk: ChunkType) -> Result<Option<Vec<u8>>, DecodingError> {
+        self.read_chunk(chunk.into(), self.metadata_limit())
+    }
+
+    // Returns the raw bytes of the XMP metadata, or None if there is no XMP metadata.
+    pub fn xmp_metadata(&mut self) -> Result<Option<Vec<u8>>, DecodingError> {
+        self.read_chunk(WebPRiffChunk::XMP, self.metadata_limit())
+    }
+
+    /// Returns true if the image has XMP metadata, without reading it.
+    pub fn has_xmp(&self) -> bool {
+        self.chunks.contains_key(&WebPRiffChunk::XMP)
+    }
+
+    /// Parses the header of the VP8 bitstream without decoding the image. For animated images,
+    /// this is the first frame.
+    ///
+    /// Returns `None` for lossless images.
+    pub fn vp8_frame_header(&mut self) -> Result<Option<Vp8Header>, DecodingError> {
+        let Some(range) = self.chunks.get(&WebPRiffChunk::VP8).cloned() else {
+            return Ok(None);
+        };
+        let mut decoder = Vp8Decoder::new(range_reader(&mut self.r, range)?);
+        decoder.decode_frame_header().map(Some)
+    }
+
+    /// Parses the transforms of the VP8L bitstream in the order they were applied by the encoder,
+    /// without decoding the image. For animated images, this is the first frame.
+    ///
+    /// The data of the transforms, like the predictor modes or the palette, has to be decoded to
+    /// reach the next transform, but the pixels are not. Returns an empty list for lossy images.
+    pub fn lossless_transforms(&mut self) -> Result<Vec<LosslessTransform>, DecodingError> {
+        let Some(range) = self.chunks.get(&WebPRiffChunk::VP8L).cloned() else {
+            return Ok(Vec::new());
+        };
+        let mut decoder = LosslessDecoder::new(range_reader(&mut self.r, range)?);
+        decoder.set_huffman_strategy(self.huffman_strategy);
+        decoder.set_memory_limit(self.memory_limit);
+        decoder.decode_transforms()
+    }
+
+    /// Returns how the alpha channel of the image is encoded, without decoding it. For animated
+    /// images, this is the alpha channel of the first frame.
+    ///
+    /// Returns `None` if there is no ALPH chunk, which is also the case for lossless images since
+    /// VP8L stores alpha alongside the other channels.
+    pub fn alpha_encoding_info(&mut self) -> Result<Option<AlphaEncodingInfo>, DecodingError> {
+        let Some(range) = self.chunks.get(&WebPRiffChunk::ALPH) else {
+            return Ok(None);
+        };
+        if range.is_empty() {
+            return Err(DecodingError::InvalidChunkSize);
+        }
+
+        self.r.seek(io::SeekFrom::Start(range.start))?;
+        let info_byte = self.r.read_u8()?;
+        extended::read_alpha_info(info_byte).map(Some)
     }
 
     /// Returns the number of bytes required to store the image or a single frame.
     pub fn output_buffer_size(&self) -> usize {
-        let bytes_per_pixel = if self.has_alpha() { 4 } else { 3 };
+        let bytes_per_pixel = self.output_format().bytes_per_pixel();
         self.width as usize * self.height as usize * bytes_per_pixel
     }
 
-    /// Returns the raw bytes of the image. For animated images, this is the first frame.
-    pub fn read_image(&mut self, buf: &mut [u8]) -> Result<(), DecodingError> {
-        assert_eq!(buf.len(), self.output_buffer_size());
+    /// Decodes the image like [`read_image`](Self::read_image), but with the color channels
+    /// premultiplied by alpha. For animated images, this is the first frame.
+    ///
+    /// Each channel `c` becomes `round(c * alpha / 255)`. Images without alpha are returned
+    /// unchanged. Animation frames are always blended with straight alpha, so this only applies
+    /// to the final result and is not available for [`read_frame`](Self::read_frame).
+    pub fn read_image_premultiplied(&mut self, buf: &mut [u8]) -> Result<(), DecodingError> {
+        self.read_image(buf)?;
+        if self.output_format() == PixelFormat::Rgba8 {
+            for pixel in buf.chunks_exact_mut(4) {
+                let alpha = u16::from(pixel[3]);
+                for c in &mut pixel[..3] {
+                    *c = ((u16::from(*c) * alpha + 127) / 255) as u8;
+                }
+            }
+        }
+        Ok(())
+    }
 
-        if let Some(range) = self.chunks.get(&WebPRiffChunk::VP8L) {
+    /// Returns the number of bytes required by [`read_image_luma`](Self::read_image_luma).
+    pub fn output_buffer_size_luma(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Decodes only the luminance of the image, one byte per pixel. For animated images, this is
+    /// the first frame.
+    ///
+    /// Luma is the BT.601 limited range Y' that VP8 stores, nominally between 16 and 235, as in
+    /// [`luma_histogram`](Self::luma_histogram). Still lossy images return their Y plane as is,
+    /// skipping the conversion to RGB. All other images are decoded to RGB first and converted
+    /// with the same formula libwebp uses when encoding. Alpha is ignored.
+    pub fn read_image_luma(&mut self, buf: &mut [u8]) -> Result<(), DecodingError> {
+        assert_eq!(buf.len(), self.output_buffer_size_luma());
+
+        if !self.has_animation() {
+            if let Some(range) = self.chunks.get(&WebPRiffChunk::VP8) {
+                let mut decoder =
+                    Vp8Decoder::new(range_reader(&mut self.r, range.start..range.end)?);
+                decoder.set_memory_limit(self.memory_limit);
+                let frame = decoder.decode_frame()?;
+                if u32::from(frame.width) != self.width || u32::from(frame.height) != self.height {
+                    return Err(DecodingError::InconsistentImageSizes);
+                }
+                buf.copy_from_slice(&frame.ybuf);
+                return Ok(());
+            }
+        }
+
+        let bytes_per_pixel = self.output_format().bytes_per_pixel();
+        let mut rgb = vec![0; self.output_buffer_size()];
+        self.read_image(&mut rgb)?;
+        for (luma, pixel) in buf.iter_mut().zip(rgb.chunks_exact(bytes_per_pixel)) {
+            *luma = rgb_to_luma(pixel);
+        }
+        Ok(())
+    }
+
+    /// Decodes only the alpha channel of the image into `buf`, one byte per pixel. For animated
+    /// images, this is the first frame composited onto the canvas, like
+    /// [`read_frame_at`](Self::read_frame_at) returns it, but playback is not affected.
+    ///
+    /// `buf` must hold `width * height` bytes. Lossy still images only decode their ALPH chunk,
+    /// skipping the VP8 bitstream entirely, and lossless ones skip the conversion to RGBA. Images
+    /// without an alpha channel are fully opaque.
+    pub fn read_alpha(&mut self, buf: &mut [u8]) -> Result<(), DecodingError> {
+        assert_eq!(buf.len(), self.width as usize * self.height as usize);
+
+        if !self.has_alpha() {
+            buf.fill(255);
+            return Ok(());
+        }
+
+        if self.has_animation() {
+            let rgba = self.read_first_frame_composited()?;
+            for (alpha, pixel) in buf.iter_mut().zip(rgba.chunks_exact(4)) {
+                *alpha = pixel[3];
+            }
+        } else if let Some(range) = self.chunks.get(&WebPRiffChunk::VP8L) {
             let mut frame = LosslessDecoder::new(range_reader(&mut self.r, range.clone())?);
+            frame.set_huffman_strategy(self.huffman_strategy);
+            frame.set_memory_limit(self.memory_limit);
             let frame = frame.decode_frame()?;
             if u32::from(frame.width) != self.width || u32::from(frame.height) != self.height {
                 return Err(DecodingError::InconsistentImageSizes);
             }
-
-            frame.fill_rgba(buf);
+            for (alpha, &argb) in buf.iter_mut().zip(frame.buf.iter()) {
+                *alpha = (argb >> 24) as u8;
+            }
         } else {
             let range = self
                 .chunks
-                .get(&WebPRiffChunk::VP8)
-                .ok_or(DecodingError::ChunkMissing)?;
-            // TODO: avoid cloning frame
-            let frame = Vp8Decoder::new(range_reader(&mut self.r, range.start..range.end)?)
-                .decode_frame()?
+                .get(&WebPRiffChunk::ALPH)
+                .ok_or(DecodingError::ChunkMissing(*b"ALPH"))?
                 .clone();
+            let alpha_chunk = read_alpha_chunk(
+                &mut range_reader(&mut self.r, range.start..range.end)?,
+                self.width,
+                self.height,
+                self.huffman_strategy,
+                self.memory_limit,
+            )?;
+
+            let width = self.width as usize;
+            for y in 0..self.height as usize {
+                for x in 0..width {
+                    let predictor =
+                        get_alpha_plane_predictor(x, y, width, alpha_chunk.filtering_method, buf);
+                    let index = y * width + x;
+                    buf[index] = predictor.wrapping_add(alpha_chunk.data[index]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of `u16` values required by [`read_image_u16`](Self::read_image_u16).
+    pub fn output_buffer_size_u16(&self) -> usize {
+        self.output_buffer_size()
+    }
+
+    /// Decodes the image like [`read_image`](Self::read_image), but with 16 bits per channel.
+    /// For animated images, this is the first frame.
+    ///
+    /// Each 8-bit value `v` is widened by bit replication to `v * 257`, so that 0 and 255 map to
+    /// 0 and 65535. No precision is gained, this only saves a conversion pass in pipelines that
+    /// work with 16-bit values.
+    pub fn read_image_u16(&mut self, buf: &mut [u16]) -> Result<(), DecodingError> {
+        assert_eq!(buf.len(), self.output_buffer_size_u16());
+
+        let mut narrow = vec![0; self.output_buffer_size()];
+        self.read_image(&mut narrow)?;
+        for (wide, &value) in buf.iter_mut().zip(&narrow) {
+            *wide = u16::from(value) * 257;
+        }
+        Ok(())
+    }
+
+    /// Returns the raw bytes of the image. For animated images, this is the first frame.
+    ///
+    /// RGBA output has straight alpha, see
+    /// [`read_image_premultiplied`](Self::read_image_premultiplied) for premultiplied alpha.
+    pub fn read_image(&mut self, buf: &mut [u8]) -> Result<(), DecodingError> {
+        assert_eq!(buf.len(), self.output_buffer_size());
+
+        let has_alpha = self.has_alpha();
+        if let Some(range) = self.chunks.get(&WebPRiffChunk::VP8L) {
+            let mut frame = LosslessDecoder::new(range_reader(&mut self.r, range.clone())?);
+            frame.set_huffman_strategy(self.huffman_strategy);
+            frame.set_memory_limit(self.memory_limit);
+            let frame = frame.decode_frame()?;
             if u32::from(frame.width) != self.width || u32::from(frame.height) != self.height {
                 return Err(DecodingError::InconsistentImageSizes);
             }
 
-            if self.has_alpha() {
+            // An extended file without the alpha flag decodes to RGB, even if VP8L stores alpha.
+            if has_alpha {
                 frame.fill_rgba(buf);
-
+            } else {
+                frame.fill_rgb(buf);
+            }
+        } else {
+            // The alpha channel is read first, because the decoded frame borrows the reader.
+            let alpha_chunk = if has_alpha {
                 let range = self
                     .chunks
                     .get(&WebPRiffChunk::ALPH)
-                    .ok_or(DecodingError::ChunkMissing)?
+                    .ok_or(DecodingError::ChunkMissing(*b"ALPH"))?
                     .clone();
-                let alpha_chunk = read_alpha_chunk(
+                Some(read_alpha_chunk(
                     &mut range_reader(&mut self.r, range.start..range.end)?,
                     self.width,
                     self.height,
-                )?;
+                    self.huffman_strategy,
+                    self.memory_limit,
+                )?)
+            } else {
+                None
+            };
+
+            let range = self
+                .chunks
+                .get(&WebPRiffChunk::VP8)
+                .ok_or(DecodingError::ChunkMissing(*b"VP8 "))?;
+            let mut decoder = Vp8Decoder::new(range_reader(&mut self.r, range.start..range.end)?);
+            decoder.set_memory_limit(self.memory_limit);
+            let frame = decoder.decode_frame()?;
+            if u32::from(frame.width) != self.width || u32::from(frame.height) != self.height {
+                return Err(DecodingError::InconsistentImageSizes);
+            }
+
+            if let Some(alpha_chunk) = alpha_chunk {
+                frame.fill_rgba(buf);
 
                 for y in 0..frame.height {
                     for x in 0..frame.width {
@@ -573,197 +1834,3971 @@ impl<R: Read + Seek> WebPDecoder<R> {
         Ok(())
     }
 
-    /// Reads the next frame of the animation.
+    /// Decodes the image and writes the raw pixel data to `w`.
     ///
-    /// The frame contents are written into `buf` and the method returns the delay of the frame in
-    /// milliseconds. If there are no more frames, the method returns `None` and `buf` is left
-    /// unchanged.
+    /// The bytes written are identical to the contents of the buffer filled by
+    /// [`read_image`](Self::read_image): rows from top to bottom without padding, with each pixel
+    /// stored as 8-bit RGBA if the image has an alpha channel and 8-bit RGB otherwise. For animated
+    /// images, this is the first frame.
     ///
-    /// Panics if the image is not animated.
-    pub fn read_frame(&mut self, buf: &mut [u8]) -> Result<Option<u32>, DecodingError> {
-        assert!(self.has_animation());
+    /// Neither codec can hand out rows before the whole frame has been decoded, but the frame is
+    /// converted and written one row at a time, so no output buffer for the whole image is
+    /// allocated. Compared to `read_image` with a caller-provided buffer, this saves the memory of
+    /// the RGB or RGBA image, at the cost of one write per row.
+    pub fn decode_to_writer<W: Write>(&mut self, mut w: W) -> Result<(), DecodingError> {
+        let has_alpha = self.has_alpha();
+        let mut row = vec![0; self.width as usize * self.output_format().bytes_per_pixel()];
 
-        if self.animation.loops_before_done == Some(0) {
-            return Ok(None);
-        }
+        if let Some(range) = self.chunks.get(&WebPRiffChunk::VP8L) {
+            let mut frame = LosslessDecoder::new(range_reader(&mut self.r, range.clone())?);
+            frame.set_huffman_strategy(self.huffman_strategy);
+            frame.set_memory_limit(self.memory_limit);
+            let frame = frame.decode_frame()?;
+            if u32::from(frame.width) != self.width || u32::from(frame.height) != self.height {
+                return Err(DecodingError::InconsistentImageSizes);
+            }
 
-        let ImageKind::Extended(info) = &self.kind else {
-            unreachable!()
-        };
+            for y in 0..usize::from(frame.height) {
+                if has_alpha {
+                    frame.fill_rgba_rows(y, &mut row);
+                } else {
+                    frame.fill_rgb_rows(y, &mut row);
+                }
+                w.write_all(&row)?;
+            }
+        } else {
+            // The alpha channel is read first, because the decoded frame borrows the reader.
+            let alpha = if has_alpha {
+                let range = self
+                    .chunks
+                    .get(&WebPRiffChunk::ALPH)
+                    .ok_or(DecodingError::ChunkMissing(*b"ALPH"))?
+                    .clone();
+                let alpha_chunk = read_alpha_chunk(
+                    &mut range_reader(&mut self.r, range.start..range.end)?,
+                    self.width,
+                    self.height,
+                    self.huffman_strategy,
+                    self.memory_limit,
+                )?;
 
-        self.r
-            .seek(io::SeekFrom::Start(self.animation.next_frame_start))?;
+                // Each predictor only looks at pixels before the current one, so the plane can be
+                // unfiltered in place.
+                let mut alpha = alpha_chunk.data;
+                let width = self.width as usize;
+                for y in 0..self.height as usize {
+                    for x in 0..width {
+                        let predictor = get_alpha_plane_predictor(
+                            x,
+                            y,
+                            width,
+                            alpha_chunk.filtering_method,
+                            &alpha,
+                        );
+                        let index = y * width + x;
+                        alpha[index] = predictor.wrapping_add(alpha[index]);
+                    }
+                }
+                Some(alpha)
+            } else {
+                None
+            };
 
-        let anmf_size = match read_chunk_header(&mut self.r)? {
-            (WebPRiffChunk::ANMF, size, _) if size >= 32 => size,
-            _ => return Err(DecodingError::ChunkHeaderInvalid(*b"ANMF")),
-        };
+            let range = self
+                .chunks
+                .get(&WebPRiffChunk::VP8)
+                .ok_or(DecodingError::ChunkMissing(*b"VP8 "))?;
+            let mut decoder = Vp8Decoder::new(range_reader(&mut self.r, range.start..range.end)?);
+            decoder.set_memory_limit(self.memory_limit);
+            let frame = decoder.decode_frame()?;
+            if u32::from(frame.width) != self.width || u32::from(frame.height) != self.height {
+                return Err(DecodingError::InconsistentImageSizes);
+            }
 
-        // Read ANMF chunk
-        let frame_x = extended::read_3_bytes(&mut self.r)? * 2;
-        let frame_y = extended::read_3_bytes(&mut self.r)? * 2;
-        let frame_width = extended::read_3_bytes(&mut self.r)? + 1;
-        let frame_height = extended::read_3_bytes(&mut self.r)? + 1;
-        if frame_x + frame_width > self.width || frame_y + frame_height > self.height {
-            return Err(DecodingError::FrameOutsideImage);
+            let width = usize::from(frame.width);
+            for y in 0..usize::from(frame.height) {
+                if let Some(alpha) = &alpha {
+                    frame.fill_rgba_row(y, &mut row);
+                    for (pixel, &value) in row.chunks_exact_mut(4).zip(&alpha[y * width..]) {
+                        pixel[3] = value;
+                    }
+                } else {
+                    frame.fill_rgb_row(y, &mut row);
+                }
+                w.write_all(&row)?;
+            }
         }
-        let duration = extended::read_3_bytes(&mut self.r)?;
-        let frame_info = self.r.read_u8()?;
-        let reserved = frame_info & 0b11111100;
-        if reserved != 0 {
-            return Err(DecodingError::InfoBitsInvalid {
-                name: "reserved",
-                value: reserved.into(),
-            });
+
+        Ok(())
+    }
+
+    /// Decodes the image like [`read_image`](Self::read_image) and converts the pixels from the
+    /// embedded ICC profile to sRGB.
+    ///
+    /// Images without an ICC profile are assumed to already be sRGB and are returned unchanged.
+    /// The same goes for images with a profile that cannot be parsed, unless
+    /// [`set_require_known_colorspace`](Self::set_require_known_colorspace) is enabled.
+    #[cfg(feature = "color")]
+    pub fn read_image_srgb(&mut self, buf: &mut [u8]) -> Result<(), DecodingError> {
+        self.read_image(buf)?;
+
+        if let Some(icc_profile) = self.icc_profile()? {
+            let Some(input) = qcms::Profile::new_from_slice(&icc_profile, false) else {
+                if self.require_known_colorspace {
+                    return Err(DecodingError::IccProfileInvalid);
+                }
+                return Ok(());
+            };
+            let mut srgb = qcms::Profile::new_sRGB();
+            srgb.precache_output_transform();
+
+            let data_type = if self.has_alpha() {
+                qcms::DataType::RGBA8
+            } else {
+                qcms::DataType::RGB8
+            };
+            let transform = qcms::Transform::new(&input, &srgb, data_type, qcms::Intent::default())
+                .ok_or(DecodingError::IccProfileInvalid)?;
+            transform.apply(buf);
         }
-        let use_alpha_blending = frame_info & 0b00000010 == 0;
-        let dispose = frame_info & 0b00000001 != 0;
 
-        let clear_color = if self.animation.dispose_next_frame {
-            Some(info.background_color)
-        } else {
-            None
-        };
+        Ok(())
+    }
 
-        //read normal bitstream now
-        let (chunk, chunk_size, chunk_size_rounded) = read_chunk_header(&mut self.r)?;
-        if chunk_size_rounded + 32 < anmf_size {
-            return Err(DecodingError::ChunkHeaderInvalid(chunk.to_fourcc()));
+    /// Returns whether every alpha value of the image is either 0 or 255, so that the alpha
+    /// channel can be stored as a 1-bit mask without loss. For animated images, this checks the
+    /// first frame.
+    ///
+    /// Images without an alpha channel return `false`, since they have no mask to preserve.
+    pub fn alpha_is_binary(&mut self) -> Result<bool, DecodingError> {
+        if !self.has_alpha() {
+            return Ok(false);
         }
 
-        let (frame, frame_has_alpha): (Vec<u8>, bool) = match chunk {
-            WebPRiffChunk::VP8 => {
-                let reader = (&mut self.r).take(chunk_size as u64);
-                let mut vp8_decoder = Vp8Decoder::new(reader);
-                let raw_frame = vp8_decoder.decode_frame()?;
-                if raw_frame.width as u32 != frame_width || raw_frame.height as u32 != frame_height
-                {
-                    return Err(DecodingError::InconsistentImageSizes);
-                }
-                let mut rgb_frame = vec![0; frame_width as usize * frame_height as usize * 3];
-                raw_frame.fill_rgb(&mut rgb_frame);
-                (rgb_frame, false)
+        let mut buf = vec![0; self.output_buffer_size()];
+        self.read_image(&mut buf)?;
+        Ok(buf.chunks_exact(4).all(|pixel| matches!(pixel[3], 0 | 255)))
+    }
+
+    /// Returns a histogram of the luma values of the image. For animated images, this is the
+    /// first frame composited onto the canvas, like [`read_frame_at`](Self::read_frame_at)
+    /// returns it, but playback is not affected.
+    ///
+    /// Luma is the BT.601 limited range Y' that VP8 stores, nominally between 16 and 235.
+    /// For lossy still images the histogram is taken straight from the decoded Y plane without
+    /// any color conversion. Otherwise luma is computed from the decoded RGB values with the same
+    /// formula libwebp uses when encoding. Alpha is ignored.
+    pub fn luma_histogram(&mut self) -> Result<[u32; 256], DecodingError> {
+        let mut histogram = [0u32; 256];
+
+        if self.has_animation() {
+            let rgba = self.read_first_frame_composited()?;
+            for pixel in rgba.chunks_exact(4) {
+                histogram[usize::from(rgb_to_luma(pixel))] += 1;
             }
-            WebPRiffChunk::VP8L => {
-                let reader = (&mut self.r).take(chunk_size as u64);
-                let mut lossless_decoder = LosslessDecoder::new(reader);
-                let frame = lossless_decoder.decode_frame()?;
-                if frame.width as u32 != frame_width || frame.height as u32 != frame_height {
-                    return Err(DecodingError::InconsistentImageSizes);
-                }
-                let mut rgba_frame = vec![0; frame_width as usize * frame_height as usize * 4];
-                frame.fill_rgba(&mut rgba_frame);
-                (rgba_frame, true)
+        } else if self.chunks.contains_key(&WebPRiffChunk::VP8L) {
+            let mut buf = vec![0; self.output_buffer_size()];
+            self.read_image(&mut buf)?;
+
+            let bytes_per_pixel = self.output_format().bytes_per_pixel();
+            for pixel in buf.chunks_exact(bytes_per_pixel) {
+                histogram[usize::from(rgb_to_luma(pixel))] += 1;
+            }
+        } else {
+            let range = self
+                .chunks
+                .get(&WebPRiffChunk::VP8)
+                .ok_or(DecodingError::ChunkMissing(*b"VP8 "))?;
+            let mut decoder = Vp8Decoder::new(range_reader(&mut self.r, range.clone())?);
+            decoder.set_memory_limit(self.memory_limit);
+            let frame = decoder.decode_frame()?;
+            if u32::from(frame.width) != self.width || u32::from(frame.height) != self.height {
+                return Err(DecodingError::InconsistentImageSizes);
             }
-            WebPRiffChunk::ALPH => {
-                if chunk_size_rounded + 40 < anmf_size {
-                    return Err(DecodingError::ChunkHeaderInvalid(chunk.to_fourcc()));
-                }
 
-                // read alpha
-                let next_chunk_start = self.r.stream_position()? + chunk_size_rounded as u64;
-                let mut reader = (&mut self.r).take(chunk_size as u64);
-                let alpha_chunk = read_alpha_chunk(&mut reader, frame_width, frame_height)?;
+            for &luma in &frame.ybuf {
+                histogram[usize::from(luma)] += 1;
+            }
+        }
 
-                // read opaque
-                self.r.seek(io::SeekFrom::Start(next_chunk_start))?;
-                let (next_chunk, next_chunk_size, _) = read_chunk_header(&mut self.r)?;
-                if chunk_size + next_chunk_size + 40 > anmf_size {
-                    return Err(DecodingError::ChunkHeaderInvalid(next_chunk.to_fourcc()));
-                }
+        Ok(histogram)
+    }
 
-                let mut vp8_decoder = Vp8Decoder::new((&mut self.r).take(chunk_size as u64));
-                let frame = vp8_decoder.decode_frame()?;
+    /// Decodes the image into a buffer owned by the decoder and returns a view of it. For
+    /// animated images, this is the first frame.
+    ///
+    /// The buffer is kept between calls and only grows when needed, so repeated calls do not
+    /// allocate.
+    pub fn read_image_borrowed(&mut self) -> Result<ImageView<'_>, DecodingError> {
+        let format = self.output_format();
 
-                let mut rgba_frame = vec![0; frame_width as usize * frame_height as usize * 4];
-                frame.fill_rgba(&mut rgba_frame);
+        let mut buf = std::mem::take(&mut self.image_buffer);
+        buf.resize(self.output_buffer_size(), 0);
+        let result = self.read_image(&mut buf);
+        self.image_buffer = buf;
+        result?;
 
-                for y in 0..frame.height {
-                    for x in 0..frame.width {
-                        let predictor: u8 = get_alpha_predictor(
-                            x.into(),
-                            y.into(),
-                            frame.width.into(),
-                            alpha_chunk.filtering_method,
-                            &rgba_frame,
-                        );
+        Ok(ImageView {
+            width: self.width,
+            height: self.height,
+            format,
+            data: &self.image_buffer,
+        })
+    }
 
-                        let alpha_index =
-                            usize::from(y) * usize::from(frame.width) + usize::from(x);
-                        let buffer_index = alpha_index * 4 + 3;
+    /// Decodes the image like [`read_image`](Self::read_image), but with the pixels in the given
+    /// layout. For animated images, this is the first frame.
+    ///
+    /// `buf` must hold `width * height * layout.bytes_per_pixel()` bytes. Images without alpha get
+    /// an opaque alpha channel in the four channel layouts, and the alpha channel is dropped in
+    /// the three channel ones.
+    pub fn read_image_with_layout(
+        &mut self,
+        buf: &mut [u8],
+        layout: PixelLayout,
+    ) -> Result<(), DecodingError> {
+        let bytes_per_pixel = layout.bytes_per_pixel();
+        assert_eq!(
+            buf.len(),
+            self.width as usize * self.height as usize * bytes_per_pixel
+        );
 
-                        rgba_frame[buffer_index] =
-                            predictor.wrapping_add(alpha_chunk.data[alpha_index]);
-                    }
+        let native_bytes_per_pixel = self.output_format().bytes_per_pixel();
+        if bytes_per_pixel == native_bytes_per_pixel {
+            self.read_image(buf)?;
+            if matches!(layout, PixelLayout::Bgra8 | PixelLayout::Bgr8) {
+                for pixel in buf.chunks_exact_mut(bytes_per_pixel) {
+                    pixel.swap(0, 2);
                 }
-
-                (rgba_frame, true)
             }
-            _ => return Err(DecodingError::ChunkHeaderInvalid(chunk.to_fourcc())),
-        };
+            return Ok(());
+        }
 
-        if self.animation.canvas.is_none() {
-            self.animation.canvas = Some(vec![0; (self.width * self.height * 4) as usize]);
+        let mut native = vec![0; self.output_buffer_size()];
+        self.read_image(&mut native)?;
+        for (src, dst) in native
+            .chunks_exact(native_bytes_per_pixel)
+            .zip(buf.chunks_exact_mut(bytes_per_pixel))
+        {
+            let (r, g, b) = (src[0], src[1], src[2]);
+            let a = src.get(3).copied().unwrap_or(255);
+            match layout {
+                PixelLayout::Rgba8 => dst.copy_from_slice(&[r, g, b, a]),
+                PixelLayout::Bgra8 => dst.copy_from_slice(&[b, g, r, a]),
+                PixelLayout::Rgb8 => dst.copy_from_slice(&[r, g, b]),
+                PixelLayout::Bgr8 => dst.copy_from_slice(&[b, g, r]),
+            }
         }
-        extended::composite_frame(
-            self.animation.canvas.as_mut().unwrap(),
-            self.width,
-            self.height,
-            clear_color,
-            &frame,
-            frame_x,
-            frame_y,
-            frame_width,
-            frame_height,
-            frame_has_alpha,
-            use_alpha_blending,
-        );
+        Ok(())
+    }
 
-        self.animation.dispose_next_frame = dispose;
-        self.animation.next_frame_start += anmf_size as u64 + 8;
-        self.animation.next_frame += 1;
+    /// Decodes the image like [`read_image`](Self::read_image), but only writes the rows `y` of
+    /// `buf` for which `y % row_step == row_offset`. The other rows are left unchanged. For
+    /// animated images, this is the first frame.
+    ///
+    /// This allows a coarse-to-fine display, for example by first writing the even rows and then
+    /// the odd ones with a `row_step` of 2. Both codecs have to decode every row in order, so each
+    /// call still costs a full decode; only the writes to `buf` are reduced.
+    ///
+    /// Returns [`DecodingError::InvalidParameter`] if `row_step` is zero or `row_offset` is not
+    /// less than `row_step`.
+    pub fn read_image_strided_rows(
+        &mut self,
+        buf: &mut [u8],
+        row_step: u32,
+        row_offset: u32,
+    ) -> Result<(), DecodingError> {
+        assert_eq!(buf.len(), self.output_buffer_size());
+        if row_offset >= row_step {
+            return Err(DecodingError::InvalidParameter(format!(
+                "row offset {row_offset} must be less than the row step {row_step}"
+            )));
+        }
 
-        if self.animation.next_frame >= self.num_frames {
-            self.animation.next_frame = 0;
-            if self.animation.loops_before_done.is_some() {
-                *self.animation.loops_before_done.as_mut().unwrap() -= 1;
+        let mut image = std::mem::take(&mut self.image_buffer);
+        image.resize(self.output_buffer_size(), 0);
+        let result = self.read_image(&mut image);
+        if result.is_ok() {
+            let row_bytes = buf.len() / self.height as usize;
+            for (dst, src) in buf
+                .chunks_exact_mut(row_bytes)
+                .zip(image.chunks_exact(row_bytes))
+                .skip(row_offset as usize)
+                .step_by(row_step as usize)
+            {
+                dst.copy_from_slice(src);
             }
-            self.animation.next_frame_start = self.chunks.get(&WebPRiffChunk::ANMF).unwrap().start;
-            self.animation.dispose_next_frame = true;
         }
+        self.image_buffer = image;
+        result
+    }
 
-        buf.copy_from_slice(self.animation.canvas.as_ref().unwrap());
+    /// Decodes `row_count` rows of the image starting at `start_row` into `buf`, in the same
+    /// format as [`read_image`](Self::read_image). For animated images, this is the first frame.
+    ///
+    /// `buf` only has to hold the requested band, so a caller can process a large image piece by
+    /// piece without allocating a full sized output buffer. VP8L cannot resume decoding in the
+    /// middle of an image, so each call decodes the whole image into its internal ARGB
+    /// representation and only converts the requested rows. Lossy images are fully decoded on
+    /// each call.
+    ///
+    /// Returns [`DecodingError::InvalidParameter`] if the rows extend past the bottom of the image.
+    pub fn read_image_rows(
+        &mut self,
+        start_row: u32,
+        row_count: u32,
+        buf: &mut [u8],
+    ) -> Result<(), DecodingError> {
+        let row_bytes = self.output_buffer_size() / self.height as usize;
+        assert_eq!(buf.len(), row_count as usize * row_bytes);
+        if start_row
+            .checked_add(row_count)
+            .map_or(true, |end| end > self.height)
+        {
+            return Err(DecodingError::InvalidParameter(format!(
+                "rows {start_row}..{start_row}+{row_count} exceed the image height {}",
+                self.height
+            )));
+        }
+
+        let has_alpha = self.has_alpha();
+        if let Some(range) = self.chunks.get(&WebPRiffChunk::VP8L) {
+            let mut frame = LosslessDecoder::new(range_reader(&mut self.r, range.clone())?);
+            frame.set_huffman_strategy(self.huffman_strategy);
+            frame.set_memory_limit(self.memory_limit);
+            let frame = frame.decode_frame()?;
+            if u32::from(frame.width) != self.width || u32::from(frame.height) != self.height {
+                return Err(DecodingError::InconsistentImageSizes);
+            }
+
+            if has_alpha {
+                frame.fill_rgba_rows(start_row as usize, buf);
+            } else {
+                frame.fill_rgb_rows(start_row as usize, buf);
+            }
+            return Ok(());
+        }
+
+        let mut image = std::mem::take(&mut self.image_buffer);
+        image.resize(self.output_buffer_size(), 0);
+        let result = self.read_image(&mut image);
+        if result.is_ok() {
+            let start = start_row as usize * row_bytes;
+            buf.copy_from_slice(&image[start..start + buf.len()]);
+        }
+        self.image_buffer = image;
+        result
+    }
+
+    /// Decodes the image into a newly allocated [`RawImage`]. For animated images, this is the
+    /// first frame.
+    ///
+    /// Unlike [`read_image`](Self::read_image), the buffer is sized by the decoder, and the result
+    /// records its own dimensions and pixel format.
+    pub fn decode_raw(&mut self) -> Result<RawImage, DecodingError> {
+        let format = self.output_format();
+        let mut data = vec![0; self.output_buffer_size()];
+        self.read_image(&mut data)?;
+
+        Ok(RawImage {
+            width: self.width,
+            height: self.height,
+            format,
+            data,
+        })
+    }
+
+    /// Decodes the image and reads all of its metadata at once. For animated images, this is the
+    /// first frame.
+    ///
+    /// The chunks are read in the order in which the WebP container lays them out, so the reader
+    /// mostly moves forward instead of seeking back and forth between separate calls.
+    pub fn decode_with_metadata(&mut self) -> Result<DecodedImage, DecodingError> {
+        let icc = self.icc_profile()?;
+        let RawImage {
+            width,
+            height,
+            format,
+            data: pixels,
+        } = self.decode_raw()?;
+        let exif = self.exif_metadata()?;
+        let xmp = self.xmp_metadata()?;
+
+        Ok(DecodedImage {
+            pixels,
+            width,
+            height,
+            format,
+            icc,
+            exif,
+            xmp,
+        })
+    }
+
+    /// Reads the next frame of the animation.
+    ///
+    /// The frame contents are written into `buf` and the method returns the delay of the frame in
+    /// milliseconds. If there are no more frames, the method returns `None` and `buf` is left
+    /// unchanged.
+    ///
+    /// Panics if the image is not animated.
+    pub fn read_frame(&mut self, buf: &mut [u8]) -> Result<Option<u32>, DecodingError> {
+        self.advance_frame(Some(buf))
+    }
+
+    /// Reads the next frame of the animation like [`read_frame`](Self::read_frame), but leaves
+    /// it on the internal canvas instead of copying it into a buffer.
+    ///
+    /// The canvas can then be read through [`canvas`](Self::canvas). Returns the delay of the
+    /// frame in milliseconds, or `None` if there are no more frames.
+    ///
+    /// Panics if the image is not animated.
+    pub fn read_frame_in_place(&mut self) -> Result<Option<u32>, DecodingError> {
+        self.advance_frame(None)
+    }
+
+    /// Returns the canvas of the animation as composited by the last frame read, or `None` if no
+    /// frame was read yet.
+    ///
+    /// The canvas always has RGBA pixels, even if [`output_format`](Self::output_format) is RGB.
+    /// The returned slice borrows the decoder, so it has to be dropped before reading the next
+    /// frame, which overwrites the canvas in place. The canvas is also kept up to date by
+    /// [`read_frame`](Self::read_frame), except for animations with a single frame, which
+    /// `read_frame` composites directly into its buffer, dropping the canvas.
+    pub fn canvas(&self) -> Option<&[u8]> {
+        self.animation.canvas.as_deref()
+    }
+
+    /// Composites the next frame of the animation onto the canvas, copying the canvas into `buf`
+    /// if provided.
+    fn advance_frame(&mut self, buf: Option<&mut [u8]>) -> Result<Option<u32>, DecodingError> {
+        assert!(self.has_animation());
+
+        if self.animation.loops_before_done == Some(0) {
+            return Ok(None);
+        }
+
+        let frame = self.read_anmf(self.animation.next_frame_start)?;
+        Ok(Some(self.composite_next_frame(frame, buf)))
+    }
+
+    /// Composites `frame`, which has to be the next frame of the animation, onto the canvas,
+    /// copying the canvas into `buf` if provided. Returns the duration of the frame.
+    fn composite_next_frame(&mut self, frame: AnimationFrame, mut buf: Option<&mut [u8]>) -> u32 {
+        let ImageKind::Extended(info) = &self.kind else {
+            unreachable!()
+        };
+        let frame_rect = frame.rect;
+
+        // Every loop starts from a canvas filled with the background color. After that, disposing
+        // a frame only resets the area it covered, before the next frame is drawn on top.
+        let clear_rect = if self.animation.next_frame == 0 {
+            Some(FrameRect {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            })
+        } else {
+            self.animation.dispose_rect
+        };
+        let dirty_rect = match clear_rect {
+            Some(clear_rect) => clear_rect.union(frame_rect),
+            None => frame_rect,
+        };
+
+        // A single frame animation is cleared to the background color before every frame, so
+        // nothing carries over between calls and the frame can be composited directly into `buf`.
+        // The frame cache keeps RGBA canvases, so RGB output still needs the canvas if it is on.
+        let direct = buf.is_some()
+            && self.num_frames == 1
+            && (self.has_alpha() || self.animation.frame_cache_size == 0);
+        if direct {
+            // Whatever `read_frame_in_place` left on the canvas is out of date from here on.
+            self.animation.canvas = None;
+        }
+        match buf.as_deref_mut() {
+            Some(buf) if direct && !self.has_alpha() => {
+                composite_single_frame_rgb(
+                    buf,
+                    self.width,
+                    &frame,
+                    info.background_color,
+                    self.blend_space,
+                );
+            }
+            buf => {
+                let canvas = match buf {
+                    Some(buf) if direct => buf,
+                    _ => self
+                        .animation
+                        .canvas
+                        .get_or_insert_with(|| vec![0; (self.width * self.height * 4) as usize]),
+                };
+                extended::composite_frame(
+                    canvas,
+                    self.width,
+                    self.height,
+                    clear_rect.map(|rect| (rect, info.background_color)),
+                    &frame.data,
+                    frame_rect.x,
+                    frame_rect.y,
+                    frame_rect.width,
+                    frame_rect.height,
+                    frame.has_alpha,
+                    frame.use_alpha_blending,
+                    self.blend_space,
+                );
+            }
+        }
+
+        let frame_index = self.animation.next_frame;
+        self.animation.dirty_rect = dirty_rect;
+        self.animation.dispose_rect = frame.dispose.then_some(frame_rect);
+        self.animation.next_frame_start = frame.end;
+        self.animation.next_frame += 1;
+
+        if self.animation.next_frame >= self.num_frames {
+            if let Some(loops_before_done) = self.animation.loops_before_done.as_mut() {
+                *loops_before_done -= 1;
+            }
+            self.rewind_animation();
+        }
+
+        if let (Some(canvas), Some(buf)) = (&self.animation.canvas, buf.as_deref_mut()) {
+            if !direct {
+                copy_canvas(canvas, buf, self.has_alpha());
+            }
+        }
+
+        self.report_progress(ProgressEvent::Bytes {
+            bytes_read: frame.end,
+            total_bytes: self.riff_end,
+        });
+        self.report_progress(ProgressEvent::Frame {
+            frame_index,
+            num_frames: self.num_frames,
+        });
+
+        if self.animation.frame_cache_size > 0 {
+            if self.animation.frame_cache.len() == self.animation.frame_cache_size {
+                self.animation.frame_cache.pop_front();
+            }
+            let canvas = match &self.animation.canvas {
+                Some(canvas) => canvas.clone(),
+                // The canvas is only skipped when compositing directly into an RGBA `buf`.
+                None => buf.as_deref().unwrap().to_vec(),
+            };
+            self.animation.frame_cache.push_back(CachedFrame {
+                index: frame_index,
+                duration: frame.duration,
+                canvas,
+                dispose_rect: self.animation.dispose_rect,
+                next_frame_start: self.animation.next_frame_start,
+            });
+        }
+
+        frame.duration
+    }
+
+    /// Decodes every frame of the animation and returns the composited frames in the same format as
+    /// [`read_frame`](Self::read_frame), each with its duration in milliseconds.
+    ///
+    /// The frame bitstreams are independent of each other, so they are decoded in parallel on the
+    /// rayon thread pool, and only compositing them onto the canvas happens sequentially. All
+    /// frames are held in memory at once, and fail with [`DecodingError::MemoryLimitExceeded`] if
+    /// that adds up to more than the memory limit. Afterwards, [`read_frame`](Self::read_frame)
+    /// continues from the start of the animation. The loop count is not affected.
+    ///
+    /// Panics if the image is not animated.
+    #[cfg(feature = "rayon")]
+    pub fn decode_all_frames(&mut self) -> Result<Vec<(Vec<u8>, u32)>, DecodingError> {
+        use rayon::prelude::*;
+
+        assert!(self.has_animation());
+        self.rewind_animation();
+
+        // The compressed data, the decoded pixels and the composited canvas of every frame are
+        // all held at once, so they count against the memory limit together.
+        let mut held_bytes = self.num_frames.saturating_mul(self.output_buffer_size());
+        if held_bytes > self.memory_limit {
+            return Err(DecodingError::MemoryLimitExceeded);
+        }
+        let stream_len = self.r.seek(io::SeekFrom::End(0))?;
+
+        // Reading from `self.r` is sequential, so the data of every frame is copied out first.
+        let mut frames = Vec::with_capacity(self.num_frames);
+        let mut position = self.animation.next_frame_start;
+        for _ in 0..self.num_frames {
+            let mut header = self.read_anmf_header(position)?;
+            position = header.end;
+
+            // The ALPH chunk always precedes the image chunk.
+            let chunks = &mut header.chunks;
+            let start = chunks
+                .alpha_range
+                .as_ref()
+                .map_or(chunks.image_range.start, |range| range.start);
+            if chunks.image_range.end > stream_len {
+                return Err(DecodingError::IoError(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Frame data is truncated",
+                )));
+            }
+            let len = (chunks.image_range.end - start) as usize;
+            let decoded_len = header.rect.width as usize * header.rect.height as usize * 4;
+            held_bytes = held_bytes.saturating_add(len).saturating_add(decoded_len);
+            if held_bytes > self.memory_limit {
+                return Err(DecodingError::MemoryLimitExceeded);
+            }
+            let mut data = vec![0; len];
+            self.r.seek(io::SeekFrom::Start(start))?;
+            self.r.read_exact(&mut data)?;
+
+            chunks.image_range = chunks.image_range.start - start..chunks.image_range.end - start;
+            if let Some(range) = chunks.alpha_range.as_mut() {
+                *range = range.start - start..range.end - start;
+            }
+            frames.push((header, data));
+        }
+
+        let (huffman_strategy, memory_limit) = (self.huffman_strategy, self.memory_limit);
+        let decoded = frames
+            .par_iter()
+            .map(|(header, data)| {
+                decode_frame_chunks(
+                    Cursor::new(data),
+                    &header.chunks,
+                    header.rect.width,
+                    header.rect.height,
+                    huffman_strategy,
+                    memory_limit,
+                    &mut Vp8Buffers::default(),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let loops_before_done = self.animation.loops_before_done;
+        let mut output = Vec::with_capacity(frames.len());
+        for ((header, _), (data, has_alpha)) in frames.into_iter().zip(decoded) {
+            let mut buf = vec![0; self.output_buffer_size()];
+            let duration =
+                self.composite_next_frame(header.into_frame(data, has_alpha), Some(&mut buf));
+            output.push((buf, duration));
+        }
+        self.animation.loops_before_done = loops_before_done;
+
+        Ok(output)
+    }
+
+    /// Decodes the first frame of an animation and composites it onto the canvas as RGBA, like
+    /// [`read_frame_at`](Self::read_frame_at) returns it, without affecting playback.
+    fn read_first_frame_composited(&mut self) -> Result<Vec<u8>, DecodingError> {
+        // The first frame is always drawn onto a canvas cleared to the background color, so it
+        // can be composited without going through the playback state.
+        let frame = self.read_anmf(self.chunks[&WebPRiffChunk::ANMF].start - 8)?;
+        let ImageKind::Extended(info) = &self.kind else {
+            unreachable!()
+        };
+        let canvas_rect = FrameRect {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        };
+        let mut rgba = vec![0; self.width as usize * self.height as usize * 4];
+        extended::composite_frame(
+            &mut rgba,
+            self.width,
+            self.height,
+            Some((canvas_rect, info.background_color)),
+            &frame.data,
+            frame.rect.x,
+            frame.rect.y,
+            frame.rect.width,
+            frame.rect.height,
+            frame.has_alpha,
+            frame.use_alpha_blending,
+            self.blend_space,
+        );
+        Ok(rgba)
+    }
+
+    /// Reads the header of the ANMF chunk starting at `anmf_start` and decodes its frame, without
+    /// compositing it.
+    fn read_anmf(&mut self, anmf_start: u64) -> Result<AnimationFrame, DecodingError> {
+        let header = self.read_anmf_header(anmf_start)?;
+        let (data, has_alpha) = decode_frame_chunks(
+            &mut self.r,
+            &header.chunks,
+            header.rect.width,
+            header.rect.height,
+            self.huffman_strategy,
+            self.memory_limit,
+            &mut self.vp8_buffers,
+        )?;
+        Ok(header.into_frame(data, has_alpha))
+    }
+
+    /// Reads the header of the ANMF chunk starting at `anmf_start` and locates its subchunks.
+    fn read_anmf_header(&mut self, anmf_start: u64) -> Result<AnmfHeader, DecodingError> {
+        self.r.seek(io::SeekFrom::Start(anmf_start))?;
+
+        let (anmf_size, anmf_size_rounded) = match read_chunk_header(&mut self.r)? {
+            (WebPRiffChunk::ANMF, size, size_rounded) if size >= 24 => (size, size_rounded),
+            _ => return Err(DecodingError::ChunkHeaderInvalid(*b"ANMF")),
+        };
+
+        // Read ANMF chunk
+        let frame_x = extended::read_3_bytes(&mut self.r)? * 2;
+        let frame_y = extended::read_3_bytes(&mut self.r)? * 2;
+        let frame_width = extended::read_3_bytes(&mut self.r)? + 1;
+        let frame_height = extended::read_3_bytes(&mut self.r)? + 1;
+        if frame_width > 16384 || frame_height > 16384 {
+            return Err(DecodingError::FrameTooLarge {
+                width: frame_width,
+                height: frame_height,
+            });
+        }
+        if frame_x + frame_width > self.width || frame_y + frame_height > self.height {
+            return Err(DecodingError::FrameOutsideImage);
+        }
+
+        self.decoded_pixels = self
+            .decoded_pixels
+            .saturating_add(u64::from(frame_width) * u64::from(frame_height));
+        if self.decoded_pixels > self.pixel_budget {
+            return Err(DecodingError::PixelBudgetExceeded(self.pixel_budget));
+        }
+        let duration = extended::read_3_bytes(&mut self.r)?;
+        let frame_info = self.r.read_u8()?;
+        let reserved = frame_info & 0b11111100;
+        if reserved != 0 {
+            return Err(DecodingError::InfoBitsInvalid {
+                name: "reserved",
+                value: reserved.into(),
+            });
+        }
+        let use_alpha_blending = frame_info & 0b00000010 == 0;
+        let dispose = frame_info & 0b00000001 != 0;
+
+        let frame_chunks = find_frame_chunks(
+            &mut self.r,
+            anmf_start + 24..anmf_start + 8 + u64::from(anmf_size),
+        )?;
+
+        Ok(AnmfHeader {
+            rect: FrameRect {
+                x: frame_x,
+                y: frame_y,
+                width: frame_width,
+                height: frame_height,
+            },
+            duration,
+            use_alpha_blending,
+            dispose,
+            chunks: frame_chunks,
+            end: self.skip_unknown_chunks(anmf_start + 8 + u64::from(anmf_size_rounded))?,
+        })
+    }
+
+    /// Sets how many of the most recently composited frames the decoder keeps in memory.
+    ///
+    /// [`read_frame_at`](Self::read_frame_at) returns cached frames without decoding anything, and
+    /// resumes decoding from the closest cached frame before the requested one instead of
+    /// replaying the animation from its start. This speeds up scrubbing back and forth, at the
+    /// cost of one canvas worth of memory per cached frame. Frames decoded by
+    /// [`read_frame`](Self::read_frame) are cached as well. Defaults to 0, which disables the
+    /// cache.
+    pub fn set_frame_cache_size(&mut self, k: usize) {
+        self.animation.frame_cache_size = k;
+        while self.animation.frame_cache.len() > k {
+            self.animation.frame_cache.pop_front();
+        }
+    }
+
+    /// Positions the animation so that the next call to [`read_frame`](Self::read_frame) returns
+    /// the frame with the given index.
+    ///
+    /// The frames before it still have to be decoded and composited, either from the current
+    /// position or, if the frame was already passed, from the start of the animation. They are
+    /// not copied out though. The loop count is not affected.
+    ///
+    /// Returns [`DecodingError::InvalidParameter`] if there is no frame with that index. Panics if
+    /// the image is not animated.
+    pub fn seek_frame(&mut self, index: usize) -> Result<(), DecodingError> {
+        assert!(self.has_animation());
+        if index >= self.num_frames {
+            return Err(DecodingError::InvalidParameter(format!(
+                "Frame {index} is out of range, the animation has {} frames",
+                self.num_frames
+            )));
+        }
+
+        if self.animation.next_frame > index {
+            self.rewind_animation();
+        }
+
+        let loops_before_done = self.animation.loops_before_done.take();
+        let mut result = Ok(());
+        while self.animation.next_frame < index {
+            if let Err(e) = self.advance_frame(None) {
+                result = Err(e);
+                break;
+            }
+        }
+        self.animation.loops_before_done = loops_before_done;
+        result
+    }
+
+    /// Decodes the frame with the given index, composited onto the canvas, into `buf` and returns
+    /// its duration in milliseconds.
+    ///
+    /// The animation is decoded from its start, or from a cached frame if
+    /// [`set_frame_cache_size`](Self::set_frame_cache_size) is enabled, up to the frame. If the
+    /// frame comes after the one last read, decoding continues from there instead. Afterwards,
+    /// [`read_frame`](Self::read_frame) continues with the following frame. The loop count is not
+    /// affected.
+    ///
+    /// Returns [`DecodingError::InvalidParameter`] if there is no frame with that index. Panics if
+    /// the image is not animated.
+    pub fn read_frame_at(&mut self, index: usize, buf: &mut [u8]) -> Result<u32, DecodingError> {
+        assert!(self.has_animation());
+        if index >= self.num_frames {
+            return Err(DecodingError::InvalidParameter(format!(
+                "Frame {index} is out of range, the animation has {} frames",
+                self.num_frames
+            )));
+        }
+
+        // Frames up to `next_frame` are already composited onto the canvas, so any cached frame
+        // after that is a better place to resume from.
+        let resume_point = self
+            .animation
+            .frame_cache
+            .iter()
+            .enumerate()
+            .filter(|(_, frame)| frame.index <= index)
+            .max_by_key(|(_, frame)| frame.index)
+            .map(|(i, frame)| (i, frame.index));
+        match resume_point {
+            Some((i, frame_index)) if frame_index == index => {
+                let frame = &self.animation.frame_cache[i];
+                copy_canvas(&frame.canvas, buf, self.has_alpha());
+                let duration = frame.duration;
+                self.resume_after_cached_frame(i);
+                return Ok(duration);
+            }
+            Some((i, frame_index)) if frame_index >= self.animation.next_frame => {
+                self.resume_after_cached_frame(i);
+            }
+            _ if self.animation.next_frame > index => self.rewind_animation(),
+            _ => {}
+        }
+
+        let loops_before_done = self.animation.loops_before_done.take();
+        let result = loop {
+            let frame_index = self.animation.next_frame;
+            match self.read_frame(buf) {
+                Ok(Some(duration)) if frame_index == index => break Ok(duration),
+                Ok(_) => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.animation.loops_before_done = loops_before_done;
+        result
+    }
+
+    /// Restores the decoding state after the cached frame at position `i` of the cache.
+    fn resume_after_cached_frame(&mut self, i: usize) {
+        let frame = &self.animation.frame_cache[i];
+        if self.num_frames > 1 {
+            self.animation.canvas = Some(frame.canvas.clone());
+        }
+        self.animation.dispose_rect = frame.dispose_rect;
+        self.animation.next_frame_start = frame.next_frame_start;
+        self.animation.next_frame = (frame.index + 1) % self.num_frames;
+    }
+
+    /// Decodes the frame of the animation that is displayed the longest and returns its index. For
+    /// still images, this decodes the image and returns 0.
+    ///
+    /// This is often a better thumbnail than the first frame, which may be a blank or fading
+    /// intro. The durations of all frames are scanned from their headers first, and ties go to
+    /// the earliest frame. The chosen frame is then decoded with
+    /// [`read_frame_at`](Self::read_frame_at), so `buf` receives the fully composited canvas.
+    pub fn read_representative_frame(&mut self, buf: &mut [u8]) -> Result<u32, DecodingError> {
+        if !self.has_animation() {
+            self.read_image(buf)?;
+            return Ok(0);
+        }
+
+        let durations = self.frame_durations()?;
+        let mut index = 0;
+        for (i, &duration) in durations.iter().enumerate() {
+            if duration > durations[index] {
+                index = i;
+            }
+        }
+
+        self.read_frame_at(index, buf)?;
+        Ok(index as u32)
+    }
+
+    /// Returns the total duration of one loop of the animation in milliseconds, or 0 for still
+    /// images.
+    ///
+    /// Only the frame headers are read, no pixels are decoded. Since each frame can last up to
+    /// about 4.6 hours, the sum saturates at `u32::MAX` instead of overflowing.
+    pub fn total_duration(&mut self) -> Result<u32, DecodingError> {
+        if !self.has_animation() {
+            return Ok(0);
+        }
+
+        Ok(self
+            .frame_durations()?
+            .into_iter()
+            .fold(0u32, u32::saturating_add))
+    }
+
+    /// Returns the stream position of the header of every ANMF chunk, in display order. Still
+    /// images have none.
+    ///
+    /// Only the chunk headers are read. The offsets can be stored and later passed to
+    /// [`read_frame_by_offset`](Self::read_frame_by_offset).
+    pub fn frame_offsets(&mut self) -> Result<Vec<u64>, DecodingError> {
+        let Some(range) = self.chunks.get(&WebPRiffChunk::ANMF) else {
+            return Ok(Vec::new());
+        };
+
+        let mut offsets = Vec::with_capacity(self.num_frames);
+        let mut position = range.start - 8;
+        for _ in 0..self.num_frames {
+            self.r.seek(io::SeekFrom::Start(position))?;
+            let size_rounded = match read_chunk_header(&mut self.r)? {
+                (WebPRiffChunk::ANMF, size, size_rounded) if size >= 24 => size_rounded,
+                _ => return Err(DecodingError::ChunkHeaderInvalid(*b"ANMF")),
+            };
+            offsets.push(position);
+            position = self.skip_unknown_chunks(position + 8 + u64::from(size_rounded))?;
+        }
+        Ok(offsets)
+    }
+
+    /// Returns the position, size, duration and blending of the frame with the given index.
+    ///
+    /// Only the chunk headers are read, no pixels are decoded.
+    ///
+    /// Returns [`DecodingError::InvalidParameter`] if there is no frame with that index.
+    pub fn frame_info(&mut self, index: usize) -> Result<FrameInfo, DecodingError> {
+        let offset = self.frame_offset(index)?;
+        self.r.seek(io::SeekFrom::Start(offset + 8))?;
+        read_frame_info(&mut self.r)
+    }
+
+    /// Returns the stream position of the header of the ANMF chunk of the frame with the given
+    /// index.
+    fn frame_offset(&mut self, index: usize) -> Result<u64, DecodingError> {
+        let offsets = self.frame_offsets()?;
+        match offsets.get(index) {
+            Some(&offset) => Ok(offset),
+            None => Err(DecodingError::InvalidParameter(format!(
+                "Frame {index} is out of range, the animation has {} frames",
+                offsets.len()
+            ))),
+        }
+    }
+
+    /// Decodes the frame of the ANMF chunk whose header starts at `offset`, without compositing
+    /// it onto the canvas, and returns its duration in milliseconds.
+    ///
+    /// `buf` receives only the frame's own rectangle, in the same pixel format as
+    /// [`read_frame`](Self::read_frame). The state of the animation is not affected.
+    ///
+    /// Returns [`DecodingError::ChunkHeaderInvalid`] if there is no ANMF chunk at `offset`, and
+    /// [`DecodingError::InvalidParameter`] if `buf` does not match the size of the frame.
+    pub fn read_frame_by_offset(
+        &mut self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<u32, DecodingError> {
+        Ok(self.read_uncomposited_frame(offset, buf)?.1)
+    }
+
+    /// Decodes the frame with the given index into `buf` without compositing it onto the canvas,
+    /// and returns where its rectangle sits on the canvas.
+    ///
+    /// Neither blending nor disposal are applied, so `buf` receives only the frame's own pixels,
+    /// in the same format as [`read_frame_by_offset`](Self::read_frame_by_offset). The size of
+    /// the frame can be looked up with [`frame_info`](Self::frame_info) beforehand. The state of
+    /// the animation is not affected.
+    ///
+    /// Returns [`DecodingError::InvalidParameter`] if there is no frame with that index or `buf`
+    /// does not match the size of the frame.
+    pub fn read_frame_uncomposited(
+        &mut self,
+        index: usize,
+        buf: &mut [u8],
+    ) -> Result<FrameRect, DecodingError> {
+        let offset = self.frame_offset(index)?;
+        Ok(self.read_uncomposited_frame(offset, buf)?.0)
+    }
+
+    /// Decodes the frame of the ANMF chunk whose header starts at `offset` into `buf`, and returns
+    /// its rectangle and duration.
+    fn read_uncomposited_frame(
+        &mut self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<(FrameRect, u32), DecodingError> {
+        let frame = self.read_anmf(offset)?;
+
+        let has_alpha = self.has_alpha();
+        let bytes_per_pixel = self.output_format().bytes_per_pixel();
+        let len = frame.rect.width as usize * frame.rect.height as usize * bytes_per_pixel;
+        if buf.len() != len {
+            return Err(DecodingError::InvalidParameter(format!(
+                "Buffer of {} bytes given for a frame of {len} bytes",
+                buf.len()
+            )));
+        }
+
+        match (frame.has_alpha, has_alpha) {
+            (true, true) | (false, false) => buf.copy_from_slice(&frame.data),
+            (true, false) => copy_canvas(&frame.data, buf, false),
+            (false, true) => {
+                for (rgba, rgb) in buf.chunks_exact_mut(4).zip(frame.data.chunks_exact(3)) {
+                    rgba[..3].copy_from_slice(rgb);
+                    rgba[3] = 255;
+                }
+            }
+        }
+
+        Ok((frame.rect, frame.duration))
+    }
+
+    /// Reads the duration of every frame of the animation from the ANMF headers, without decoding
+    /// any image data.
+    fn frame_durations(&mut self) -> Result<Vec<u32>, DecodingError> {
+        let mut durations = Vec::with_capacity(self.num_frames);
+        for offset in self.frame_offsets()? {
+            self.r.seek(io::SeekFrom::Start(offset + 8 + 12))?;
+            durations.push(extended::read_3_bytes(&mut self.r)?);
+        }
+        Ok(durations)
+    }
+
+    /// Restarts playback at the first frame, as if no frames had been read yet.
+    ///
+    /// This also restores the number of loops left. Does nothing for still images.
+    pub fn reset_animation(&mut self) {
+        if !self.has_animation() {
+            return;
+        }
+
+        self.rewind_animation();
+        self.animation.loops_before_done = match self.animation.loop_count {
+            0 => None,
+            n => Some(n),
+        };
+        self.animation.canvas = None;
+    }
+
+    /// Returns the position of the first chunk at or after `position` that is not an unknown
+    /// chunk, since those may appear between the frames of an animation.
+    fn skip_unknown_chunks(&mut self, mut position: u64) -> Result<u64, DecodingError> {
+        loop {
+            self.r.seek(io::SeekFrom::Start(position))?;
+            match read_chunk_header(&mut self.r) {
+                Ok((WebPRiffChunk::Unknown(_), _, size_rounded)) => {
+                    position += 8 + u64::from(size_rounded);
+                }
+                Ok(_) => return Ok(position),
+                Err(DecodingError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(position)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Makes the next call to [`read_frame`](Self::read_frame) start over at the first frame.
+    fn rewind_animation(&mut self) {
+        self.animation.next_frame = 0;
+        self.animation.next_frame_start = self.chunks.get(&WebPRiffChunk::ANMF).unwrap().start - 8;
+        self.animation.dispose_rect = None;
+    }
+
+    /// Returns whether the first frame of the animation fully determines its canvas.
+    ///
+    /// This is the case if the frame is a VP8 keyframe or a VP8L image, covers the whole canvas
+    /// and replaces the canvas instead of being blended onto it. Blending a frame without alpha
+    /// replaces the canvas as well. A player can then start rendering at the first frame without
+    /// depending on the background color. Still images always return true.
+    pub fn first_frame_is_full_keyframe(&mut self) -> Result<bool, DecodingError> {
+        let Some(range) = self.chunks.get(&WebPRiffChunk::ANMF).cloned() else {
+            return Ok(true);
+        };
+
+        self.r.seek(io::SeekFrom::Start(range.start))?;
+        let frame_x = extended::read_3_bytes(&mut self.r)? * 2;
+        let frame_y = extended::read_3_bytes(&mut self.r)? * 2;
+        let frame_width = extended::read_3_bytes(&mut self.r)? + 1;
+        let frame_height = extended::read_3_bytes(&mut self.r)? + 1;
+        let _duration = extended::read_3_bytes(&mut self.r)?;
+        let use_alpha_blending = self.r.read_u8()? & 0b00000010 == 0;
+
+        let frame_chunks = find_frame_chunks(&mut self.r, range.start + 16..range.end)?;
+        let (keyframe, opaque) = if frame_chunks.image == WebPRiffChunk::VP8 {
+            self.r
+                .seek(io::SeekFrom::Start(frame_chunks.image_range.start))?;
+            let tag = self.r.read_u24::<LittleEndian>()?;
+            (tag & 1 == 0, frame_chunks.alpha_range.is_none())
+        } else {
+            (true, false)
+        };
+
+        Ok(keyframe
+            && frame_x == 0
+            && frame_y == 0
+            && frame_width == self.width
+            && frame_height == self.height
+            && (!use_alpha_blending || opaque))
+    }
+
+    /// Decodes one loop of the animation, starting at the next frame, and merges consecutive
+    /// frames that composite to identical canvases.
+    ///
+    /// Returns each distinct canvas along with the total duration in milliseconds of the frames it
+    /// stands for. Since every distinct frame is kept in memory, this is intended for
+    /// animations that are small or have long static sections.
+    ///
+    /// Panics if the image is not animated.
+    pub fn read_distinct_frames(&mut self) -> Result<Vec<(u32, Vec<u8>)>, DecodingError> {
+        let mut frames: Vec<(u32, Vec<u8>)> = Vec::new();
+        let mut buf = vec![0; self.output_buffer_size()];
+
+        for _ in 0..self.num_frames {
+            let Some(duration) = self.read_frame(&mut buf)? else {
+                break;
+            };
+            match frames.last_mut() {
+                Some((total, last)) if *last == buf => *total = total.saturating_add(duration),
+                _ => frames.push((duration, buf.clone())),
+            }
+        }
+
+        Ok(frames)
+    }
+
+    /// Decodes one loop of the animation, starting at the next frame, resampled to a constant
+    /// frame rate.
+    ///
+    /// Output frame `n` is the canvas as it is displayed at `n / fps` seconds, so frames are
+    /// repeated or dropped as needed to match their durations. The iterator ends once the whole
+    /// loop has been covered. If every frame has a duration of zero, the loop takes no time at
+    /// all, and the final canvas is returned as the only frame.
+    ///
+    /// Returns an error if `fps` is zero. Panics if the image is not animated.
+    pub fn read_frames_at_fps(
+        &mut self,
+        fps: u32,
+    ) -> Result<impl Iterator<Item = Result<Vec<u8>, DecodingError>> + '_, DecodingError> {
+        assert!(self.has_animation());
+        if fps == 0 {
+            return Err(DecodingError::InvalidParameter(
+                "Frame rate must be non-zero".to_owned(),
+            ));
+        }
+
+        let mut buf = vec![0; self.output_buffer_size()];
+        let mut frames_left = self.num_frames;
+        let mut done = false;
+        let mut failed = false;
+        // Both in milliseconds multiplied by `fps`, so that no rounding is needed.
+        let mut frame_end = 0u64;
+        let mut output_time = 0u64;
+
+        Ok(std::iter::from_fn(move || {
+            while !done && frame_end <= output_time {
+                if frames_left == 0 {
+                    done = true;
+                    break;
+                }
+                match self.read_frame(&mut buf) {
+                    Ok(Some(duration)) => {
+                        frame_end += u64::from(duration) * u64::from(fps);
+                        frames_left -= 1;
+                    }
+                    Ok(None) => done = true,
+                    Err(e) => {
+                        done = true;
+                        failed = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+            // A loop of frames without duration ends before the first output frame, but it still
+            // leaves its final canvas on screen.
+            let zero_length = output_time == 0 && frames_left < self.num_frames;
+            if done && (failed || !zero_length) {
+                return None;
+            }
+
+            output_time += 1000;
+            Some(Ok(buf.clone()))
+        }))
+    }
+
+    /// Decodes this image and `other` and returns whether their pixels are identical.
+    ///
+    /// RGB and RGBA images compare equal if the RGBA image is fully opaque. Animations are
+    /// compared frame by frame, starting at the next frame of each, for one loop. Frame durations
+    /// are not compared, and an animation never equals a still image.
+    pub fn pixels_equal<R2: Read + Seek>(
+        &mut self,
+        other: &mut WebPDecoder<R2>,
+    ) -> Result<bool, DecodingError> {
+        if self.dimensions() != other.dimensions()
+            || self.has_animation() != other.has_animation()
+            || self.has_animation() && self.num_frames != other.num_frames
+        {
+            return Ok(false);
+        }
+
+        fn rgba(buf: &[u8], has_alpha: bool) -> impl Iterator<Item = [u8; 4]> + '_ {
+            let bytes_per_pixel = if has_alpha { 4 } else { 3 };
+            buf.chunks_exact(bytes_per_pixel)
+                .map(move |p| [p[0], p[1], p[2], if has_alpha { p[3] } else { 255 }])
+        }
+
+        let mut buf = vec![0; self.output_buffer_size()];
+        let mut other_buf = vec![0; other.output_buffer_size()];
+
+        if !self.has_animation() {
+            self.read_image(&mut buf)?;
+            other.read_image(&mut other_buf)?;
+            return Ok(rgba(&buf, self.has_alpha()).eq(rgba(&other_buf, other.has_alpha())));
+        }
+
+        for _ in 0..self.num_frames {
+            match (
+                self.read_frame(&mut buf)?,
+                other.read_frame(&mut other_buf)?,
+            ) {
+                (Some(_), Some(_)) => {}
+                (None, None) => break,
+                _ => return Ok(false),
+            }
+            if !rgba(&buf, self.has_alpha()).eq(rgba(&other_buf, other.has_alpha())) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Reads the next frame of the animation like [`read_frame`](Self::read_frame), and also
+    /// returns the region of the canvas that may differ from the previous frame.
+    ///
+    /// Pixels outside of the returned rectangle are guaranteed to be unchanged, so a renderer only
+    /// needs to upload that part of `buf`. The rectangle covers the whole canvas for the first
+    /// frame of every loop.
+    pub fn read_frame_dirty(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Result<Option<(u32, FrameRect)>, DecodingError> {
+        Ok(self
+            .read_frame(buf)?
+            .map(|duration| (duration, self.animation.dirty_rect)))
+    }
+
+    /// Returns an iterator over the frames of the image and their durations in milliseconds.
+    ///
+    /// The frames are in the same format as for [`read_frame`](Self::read_frame), and the
+    /// iterator ends when `read_frame` would return `None`. For animations that loop forever,
+    /// it never ends. Still images yield a single frame with a duration of zero.
+    pub fn frames(&mut self) -> Frames<'_, R> {
+        Frames {
+            buf: vec![0; self.output_buffer_size()],
+            decoder: self,
+            done: false,
+        }
+    }
+}
+
+/// An iterator over the frames of an image, created by [`WebPDecoder::frames`].
+pub struct Frames<'a, R> {
+    decoder: &'a mut WebPDecoder<R>,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read + Seek> Iterator for Frames<'_, R> {
+    type Item = Result<(Vec<u8>, u32), DecodingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = if self.decoder.has_animation() {
+            self.decoder.read_frame(&mut self.buf)
+        } else {
+            self.done = true;
+            self.decoder.read_image(&mut self.buf).map(|()| Some(0))
+        };
+        match result {
+            Ok(Some(duration)) => Some(Ok((self.buf.clone(), duration))),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Converts the RGB values at the start of `pixel` to BT.601 limited range luma, like libwebp does
+/// when encoding.
+fn rgb_to_luma(pixel: &[u8]) -> u8 {
+    let (r, g, b) = (
+        u32::from(pixel[0]),
+        u32::from(pixel[1]),
+        u32::from(pixel[2]),
+    );
+    ((16839 * r + 33059 * g + 6420 * b + (16 << 16) + (1 << 15)) >> 16) as u8
+}
+
+/// Copies an RGBA canvas into an output buffer, dropping the alpha channel unless `has_alpha`.
+/// Composites the only frame of an animation onto a canvas of the background color like
+/// `composite_frame`, but writes RGB pixels to `buf`, going through one RGBA row at a time
+/// instead of a full canvas.
+fn composite_single_frame_rgb(
+    buf: &mut [u8],
+    canvas_width: u32,
+    frame: &AnimationFrame,
+    background_color: [u8; 4],
+    blend_space: BlendSpace,
+) {
+    let rect = frame.rect;
+    let bytes_per_pixel = if frame.has_alpha { 4 } else { 3 };
+    let frame_row_bytes = rect.width as usize * bytes_per_pixel;
+    let row_rect = FrameRect {
+        x: 0,
+        y: 0,
+        width: canvas_width,
+        height: 1,
+    };
+
+    let mut row = vec![0; canvas_width as usize * 4];
+    for (y, rgb_row) in (0..).zip(buf.chunks_exact_mut(canvas_width as usize * 3)) {
+        if (rect.y..rect.y + rect.height).contains(&y) {
+            let frame_row =
+                &frame.data[(y - rect.y) as usize * frame_row_bytes..][..frame_row_bytes];
+            extended::composite_frame(
+                &mut row,
+                canvas_width,
+                1,
+                Some((row_rect, background_color)),
+                frame_row,
+                rect.x,
+                0,
+                rect.width,
+                1,
+                frame.has_alpha,
+                frame.use_alpha_blending,
+                blend_space,
+            );
+        } else {
+            for pixel in row.chunks_exact_mut(4) {
+                pixel.copy_from_slice(&background_color);
+            }
+        }
+        copy_canvas(&row, rgb_row, false);
+    }
+}
+
+fn copy_canvas(canvas: &[u8], buf: &mut [u8], has_alpha: bool) {
+    if has_alpha {
+        buf.copy_from_slice(canvas);
+    } else {
+        for (rgb, rgba) in buf.chunks_exact_mut(3).zip(canvas.chunks_exact(4)) {
+            rgb.copy_from_slice(&rgba[..3]);
+        }
+    }
+}
+
+/// Returns which parts of the WebP format this build of the decoder supports.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        vp8_keyframes: true,
+        vp8_interframes: false,
+        vp8l: true,
+        alpha_uncompressed: true,
+        alpha_lossless: true,
+        animation: true,
+        icc_profile: true,
+        exif: true,
+        xmp: true,
+        color_management: cfg!(feature = "color"),
+    }
+}
+
+/// Classifies a WebP file from its first 16 bytes.
+///
+/// Only the RIFF and WEBP signatures and the fourcc of the first chunk are checked, so this is a
+/// cheap pre-filter and not a guarantee that the file decodes. Returns `None` if `prefix` is
+/// shorter than 16 bytes or is not the start of a WebP file.
+pub fn quick_classify(prefix: &[u8]) -> Option<WebPClass> {
+    let prefix = prefix.get(..16)?;
+    if &prefix[0..4] != b"RIFF" || &prefix[8..12] != b"WEBP" {
+        return None;
+    }
+
+    match &prefix[12..16] {
+        b"VP8 " => Some(WebPClass::Lossy),
+        b"VP8L" => Some(WebPClass::Lossless),
+        b"VP8X" => Some(WebPClass::Extended),
+        _ => None,
+    }
+}
+
+pub(crate) fn range_reader<R: Read + Seek>(
+    mut r: R,
+    range: Range<u64>,
+) -> Result<impl Read, DecodingError> {
+    r.seek(io::SeekFrom::Start(range.start))?;
+    Ok(r.take(range.end - range.start))
+}
+
+/// Records which decoders the image data chunk `chunk` requires. Returns the number of bytes of the
+/// chunk's payload that were consumed from `r`.
+fn inspect_image_chunk<R: Read>(
+    mut r: R,
+    chunk: WebPRiffChunk,
+    chunk_size: u32,
+    is_lossy: &mut bool,
+    uses_vp8l: &mut bool,
+) -> Result<i64, DecodingError> {
+    match chunk {
+        WebPRiffChunk::VP8 => *is_lossy = true,
+        WebPRiffChunk::VP8L => *uses_vp8l = true,
+        // The spec says that lossless images SHOULD NOT contain ALPH chunks, so we treat them as
+        // an indicator of lossy images. Alpha compressed with method 1 is a VP8L bitstream.
+        WebPRiffChunk::ALPH if chunk_size > 0 => {
+            *is_lossy = true;
+            if r.read_u8()? & 0b00000011 == 1 {
+                *uses_vp8l = true;
+            }
+            return Ok(1);
+        }
+        _ => {}
+    }
+    Ok(0)
+}
+
+/// Finds the value of the orientation tag (0x0112) in the first IFD of EXIF data.
+fn read_exif_orientation(exif: &[u8]) -> Option<u16> {
+    // Some encoders keep the APP1 marker's "Exif" prefix in front of the TIFF header.
+    let tiff = exif.strip_prefix(b"Exif\0\0").unwrap_or(exif);
+    let big_endian = match tiff.get(..4)? {
+        b"II*\0" => false,
+        b"MM\0*" => true,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |offset: usize| {
+        let bytes = tiff.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+
+    let ifd = usize::try_from(u32_at(4)?).ok()?;
+    let num_entries = usize::from(u16_at(ifd)?);
+    (0..num_entries).find_map(|i| {
+        let entry = ifd + 2 + 12 * i;
+        // The orientation is a single SHORT, stored at the start of the value field.
+        (u16_at(entry)? == 0x0112 && u16_at(entry + 2)? == 3).then(|| u16_at(entry + 8))?
+    })
+}
+
+/// Reads the 16 byte frame header at the start of an ANMF chunk's data.
+fn read_frame_info<R: Read>(mut r: R) -> Result<FrameInfo, DecodingError> {
+    let x = extended::read_3_bytes(&mut r)? * 2;
+    let y = extended::read_3_bytes(&mut r)? * 2;
+    let width = extended::read_3_bytes(&mut r)? + 1;
+    let height = extended::read_3_bytes(&mut r)? + 1;
+    let duration_ms = extended::read_3_bytes(&mut r)?;
+    let flags = r.read_u8()?;
+    Ok(FrameInfo {
+        x,
+        y,
+        width,
+        height,
+        duration_ms,
+        use_alpha_blending: flags & 0b00000010 == 0,
+        dispose: flags & 0b00000001 != 0,
+    })
+}
+
+/// Reads the dimensions from the header of a VP8 or VP8L bitstream.
+fn read_bitstream_dimensions<R: Read>(
+    mut r: R,
+    chunk: WebPRiffChunk,
+) -> Result<(u32, u32), DecodingError> {
+    if chunk == WebPRiffChunk::VP8 {
+        let mut header = [0u8; 10];
+        r.read_exact(&mut header)?;
+        let w = u16::from_le_bytes([header[6], header[7]]);
+        let h = u16::from_le_bytes([header[8], header[9]]);
+        Ok((u32::from(w & 0x3FFF), u32::from(h & 0x3FFF)))
+    } else {
+        let _signature = r.read_u8()?;
+        let header = r.read_u32::<LittleEndian>()?;
+        Ok(((header & 0x3FFF) + 1, ((header >> 14) & 0x3FFF) + 1))
+    }
+}
+
+/// The image data subchunks of an animation frame.
+struct FrameChunks {
+    /// Either VP8 or VP8L
+    image: WebPRiffChunk,
+    image_range: Range<u64>,
+    alpha_range: Option<Range<u64>>,
+}
+
+/// Locates the image data of an animation frame, given the range of the subchunks that follow the
+/// 16 byte frame header of an ANMF chunk.
+///
+/// Subchunk positions are derived from the padded sizes in their headers. Since some encoders do
+/// not pad odd sized ALPH chunks, the unpadded position is used if the padded one does not contain
+/// a VP8 chunk.
+fn find_frame_chunks<R: Read + Seek>(
+    r: &mut R,
+    range: Range<u64>,
+) -> Result<FrameChunks, DecodingError> {
+    let mut position = range.start;
+    let mut alpha_range = None;
+
+    loop {
+        if position + 8 > range.end {
+            return Err(DecodingError::ChunkMissing(*b"VP8 "));
+        }
+        r.seek(io::SeekFrom::Start(position))?;
+        let (chunk, chunk_size, chunk_size_rounded) = read_chunk_header(&mut *r)?;
+
+        let chunk_range = position + 8..position + 8 + u64::from(chunk_size);
+        if chunk_range.end > range.end {
+            return Err(DecodingError::ChunkHeaderInvalid(chunk.to_fourcc()));
+        }
+
+        match chunk {
+            WebPRiffChunk::ALPH if alpha_range.is_none() => {
+                position = chunk_range.start + u64::from(chunk_size_rounded);
+                if chunk_size != chunk_size_rounded && position + 4 <= range.end {
+                    r.seek(io::SeekFrom::Start(position))?;
+                    if read_fourcc(&mut *r)? != WebPRiffChunk::VP8 {
+                        position = chunk_range.end;
+                    }
+                }
+                alpha_range = Some(chunk_range);
+            }
+            WebPRiffChunk::VP8 | WebPRiffChunk::VP8L
+                if chunk == WebPRiffChunk::VP8 || alpha_range.is_none() =>
+            {
+                return Ok(FrameChunks {
+                    image: chunk,
+                    image_range: chunk_range,
+                    alpha_range,
+                })
+            }
+            _ => return Err(DecodingError::ChunkHeaderInvalid(chunk.to_fourcc())),
+        }
+    }
+}
+
+/// Decodes the image data of an animation frame located by `find_frame_chunks`. Returns RGBA
+/// pixels if the frame has alpha and RGB otherwise, along with whether it has alpha.
+fn decode_frame_chunks<R: Read + Seek>(
+    mut r: R,
+    chunks: &FrameChunks,
+    frame_width: u32,
+    frame_height: u32,
+    huffman_strategy: HuffmanStrategy,
+    memory_limit: usize,
+    vp8_buffers: &mut Vp8Buffers,
+) -> Result<(Vec<u8>, bool), DecodingError> {
+    if chunks.image == WebPRiffChunk::VP8L {
+        let reader = range_reader(&mut r, chunks.image_range.clone())?;
+        let mut lossless_decoder = LosslessDecoder::new(reader);
+        lossless_decoder.set_huffman_strategy(huffman_strategy);
+        lossless_decoder.set_memory_limit(memory_limit);
+        let frame = lossless_decoder.decode_frame()?;
+        if frame.width as u32 != frame_width || frame.height as u32 != frame_height {
+            return Err(DecodingError::InconsistentImageSizes);
+        }
+        let mut rgba_frame = vec![0; frame_width as usize * frame_height as usize * 4];
+        frame.fill_rgba(&mut rgba_frame);
+        Ok((rgba_frame, true))
+    } else {
+        let alpha_chunk = match chunks.alpha_range.clone() {
+            Some(alpha_range) => Some(read_alpha_chunk(
+                &mut range_reader(&mut r, alpha_range)?,
+                frame_width,
+                frame_height,
+                huffman_strategy,
+                memory_limit,
+            )?),
+            None => None,
+        };
+
+        let mut vp8_decoder = Vp8Decoder::with_buffers(
+            range_reader(&mut r, chunks.image_range.clone())?,
+            std::mem::take(vp8_buffers),
+        );
+        vp8_decoder.set_memory_limit(memory_limit);
+        let frame = vp8_decoder.decode_frame()?;
+        if frame.width as u32 != frame_width || frame.height as u32 != frame_height {
+            // The frame rectangle most likely has the upscaled size in this case.
+            if frame.horizontal_scale != 0 || frame.vertical_scale != 0 {
+                return Err(DecodingError::UnsupportedFeature(
+                    "VP8 frame upscaling".to_owned(),
+                ));
+            }
+            return Err(DecodingError::InconsistentImageSizes);
+        }
+
+        let decoded = match alpha_chunk {
+            None => {
+                let mut rgb_frame = vec![0; frame_width as usize * frame_height as usize * 3];
+                frame.fill_rgb(&mut rgb_frame);
+                (rgb_frame, false)
+            }
+            Some(alpha_chunk) => {
+                let mut rgba_frame = vec![0; frame_width as usize * frame_height as usize * 4];
+                frame.fill_rgba(&mut rgba_frame);
+
+                for y in 0..frame.height {
+                    for x in 0..frame.width {
+                        let predictor: u8 = get_alpha_predictor(
+                            x.into(),
+                            y.into(),
+                            frame.width.into(),
+                            alpha_chunk.filtering_method,
+                            &rgba_frame,
+                        );
+
+                        let alpha_index =
+                            usize::from(y) * usize::from(frame.width) + usize::from(x);
+                        let buffer_index = alpha_index * 4 + 3;
+
+                        rgba_frame[buffer_index] =
+                            predictor.wrapping_add(alpha_chunk.data[alpha_index]);
+                    }
+                }
+
+                (rgba_frame, true)
+            }
+        };
+
+        *vp8_buffers = vp8_decoder.into_buffers();
+        Ok(decoded)
+    }
+}
+
+pub(crate) fn read_fourcc<R: Read>(mut r: R) -> Result<WebPRiffChunk, DecodingError> {
+    let mut chunk_fourcc = [0; 4];
+    r.read_exact(&mut chunk_fourcc)?;
+    Ok(WebPRiffChunk::from_fourcc(chunk_fourcc))
+}
+
+pub(crate) fn read_chunk_header<R: Read>(
+    mut r: R,
+) -> Result<(WebPRiffChunk, u32, u32), DecodingError> {
+    let chunk = read_fourcc(&mut r)?;
+    let chunk_size = r.read_u32::<LittleEndian>()?;
+    let chunk_size_rounded = chunk_size.saturating_add(chunk_size & 1);
+    Ok((chunk, chunk_size, chunk_size_rounded))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    #[cfg(feature = "benchmarks")]
+    extern crate test;
+    use super::*;
+    use crate::lossless::test::solid_color_image;
+    use crate::vp8::FilterType;
+
+    const VP8X_ALPHA: u8 = 0b00010000;
+    const VP8X_ANIMATION: u8 = 0b00000010;
+    const VP8X_EXIF: u8 = 0b00001000;
+
+    /// Serializes a chunk, including its header and padding byte.
+    fn chunk(fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = fourcc.to_vec();
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn webp(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"WEBP".to_vec();
+        for chunk in chunks {
+            data.extend_from_slice(chunk);
+        }
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&data);
+        bytes
+    }
+
+    fn vp8x(flags: u8, width: u32, height: u32) -> Vec<u8> {
+        let mut data = vec![flags, 0, 0, 0];
+        data.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        data.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        chunk(b"VP8X", &data)
+    }
+
+    fn anim(background_color: [u8; 4], loop_count: u16) -> Vec<u8> {
+        let mut data = background_color.to_vec();
+        data.extend_from_slice(&loop_count.to_le_bytes());
+        chunk(b"ANIM", &data)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn anmf(
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        duration: u32,
+        flags: u8,
+        subchunks: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(x / 2).to_le_bytes()[..3]);
+        data.extend_from_slice(&(y / 2).to_le_bytes()[..3]);
+        data.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        data.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        data.extend_from_slice(&duration.to_le_bytes()[..3]);
+        data.push(flags);
+        for subchunk in subchunks {
+            data.extend_from_slice(subchunk);
+        }
+        chunk(b"ANMF", &data)
+    }
+
+    /// A solid colored lossless frame given as `(x, y, width, height, duration, flags, rgba)`.
+    pub(crate) type SolidFrame = (u32, u32, u32, u32, u32, u8, [u8; 4]);
+
+    pub(crate) fn solid_animation(
+        width: u32,
+        height: u32,
+        background_color: [u8; 4],
+        frames: &[SolidFrame],
+    ) -> Vec<u8> {
+        let mut chunks = vec![
+            vp8x(VP8X_ALPHA | VP8X_ANIMATION, width, height),
+            anim(background_color, 0),
+        ];
+        for &(x, y, w, h, duration, flags, rgba) in frames {
+            let frame = solid_color_image(w as u16, h as u16, rgba);
+            chunks.push(anmf(x, y, w, h, duration, flags, &[chunk(b"VP8L", &frame)]));
+        }
+        webp(&chunks)
+    }
+
+    /// Returns the VP8 chunk, including its header, of a lossy test image.
+    fn lossy_chunk() -> (Vec<u8>, u32, u32) {
+        let bytes = include_bytes!("../tests/images/1.webp");
+        (bytes[12..].to_vec(), 550, 368)
+    }
+
+    fn new_decoder(bytes: Vec<u8>) -> WebPDecoder<Cursor<Vec<u8>>> {
+        WebPDecoder::new(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn required_decoders() {
+        let still = |file: &str| {
+            let bytes = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+            let decoder = new_decoder(bytes);
+            (decoder.requires_vp8(), decoder.requires_vp8l())
+        };
+        assert_eq!(still("1"), (true, false));
+        assert_eq!(still("1_webp_ll"), (false, true));
+        assert_eq!(still("1_webp_a"), (true, true));
+
+        let lossless = solid_animation(4, 4, [0; 4], &[(0, 0, 4, 4, 100, 0, [1, 2, 3, 4])]);
+        let decoder = new_decoder(lossless);
+        assert!(!decoder.requires_vp8());
+        assert!(decoder.requires_vp8l());
+
+        let (vp8, width, height) = lossy_chunk();
+        let lossy = webp(&[
+            vp8x(VP8X_ANIMATION, width, height),
+            anim([0; 4], 0),
+            anmf(0, 0, width, height, 100, 0, &[vp8]),
+        ]);
+        let decoder = new_decoder(lossy);
+        assert!(decoder.requires_vp8());
+        assert!(!decoder.requires_vp8l());
+    }
+
+    #[test]
+    fn background_color_order() {
+        // Stored as BGRA, so this is blue.
+        let bytes = solid_animation(2, 1, [255, 0, 0, 255], &[(0, 0, 1, 1, 10, 0b10, [0; 4])]);
+        let mut buf = [0; 8];
+        new_decoder(bytes).read_frame(&mut buf).unwrap();
+        assert_eq!(buf, [0, 0, 0, 0, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn background_color() {
+        let bytes = solid_animation(2, 1, [1, 2, 3, 4], &[(0, 0, 1, 1, 10, 0b10, [0; 4])]);
+        let mut decoder = new_decoder(bytes);
+        assert_eq!(decoder.background_color(), Some([3, 2, 1, 4]));
+        decoder.set_background_color([5, 6, 7, 8]).unwrap();
+        assert_eq!(decoder.background_color(), Some([5, 6, 7, 8]));
+
+        let vp8l = chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 4]));
+        assert_eq!(new_decoder(webp(&[vp8l])).background_color(), None);
+    }
+
+    #[test]
+    fn single_frame_animation() {
+        let bytes = solid_animation(
+            4,
+            4,
+            [9, 9, 9, 9],
+            &[(2, 2, 2, 2, 100, 0b10, [255, 0, 0, 255])],
+        );
+        let mut decoder = new_decoder(bytes);
+
+        let mut expected = vec![9; 4 * 4 * 4];
+        for y in 2..4 {
+            for x in 2..4 {
+                expected[(y * 4 + x) * 4..][..4].copy_from_slice(&[255, 0, 0, 255]);
+            }
+        }
+
+        for _ in 0..2 {
+            let mut buf = vec![0; 4 * 4 * 4];
+            assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(100));
+            assert_eq!(buf, expected);
+        }
+        assert!(decoder.animation.canvas.is_none());
+
+        // Without an alpha channel, the frame is blended onto the background row by row.
+        let frame = solid_color_image(2, 2, [255, 0, 0, 128]);
+        let bytes = webp(&[
+            vp8x(VP8X_ANIMATION, 4, 4),
+            anim([9, 9, 9, 255], 0),
+            anmf(2, 2, 2, 2, 100, 0, &[chunk(b"VP8L", &frame)]),
+        ]);
+        let mut decoder = new_decoder(bytes.clone());
+        decoder.read_frame_in_place().unwrap();
+        let expected: Vec<u8> = decoder
+            .canvas()
+            .unwrap()
+            .chunks_exact(4)
+            .flat_map(|pixel| &pixel[..3])
+            .copied()
+            .collect();
+        assert_eq!(expected[..3], [9, 9, 9]);
+        assert_ne!(expected[expected.len() - 3..], [9, 9, 9]);
+
+        let mut buf = vec![0; 4 * 4 * 3];
+        assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(100));
+        assert_eq!(buf, expected);
+        assert!(decoder.canvas().is_none());
+
+        // The frame cache stores the RGBA canvas, so it is still composited there.
+        let mut decoder = new_decoder(bytes);
+        decoder.set_frame_cache_size(1);
+        let mut buf = vec![0; 4 * 4 * 3];
+        decoder.read_frame(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+        assert!(decoder.canvas().is_some());
+    }
+
+    #[test]
+    fn multi_frame_animation() {
+        let bytes = solid_animation(
+            2,
+            1,
+            [0; 4],
+            &[
+                (0, 0, 2, 1, 50, 0b10, [1, 1, 1, 255]),
+                (0, 0, 1, 1, 70, 0b10, [2, 2, 2, 255]),
+            ],
+        );
+        let mut decoder = new_decoder(bytes);
+
+        let mut buf = vec![0; 2 * 4];
+        assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(50));
+        assert_eq!(buf, [1, 1, 1, 255, 1, 1, 1, 255]);
+        assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(70));
+        assert_eq!(buf, [2, 2, 2, 255, 1, 1, 1, 255]);
+        assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(50));
+        assert_eq!(buf, [1, 1, 1, 255, 1, 1, 1, 255]);
+    }
+
+    #[test]
+    fn lossless_header_dimensions() {
+        let header = |width: u32, height: u32, alpha: u32, version: u32| {
+            let bits = (width - 1) | (height - 1) << 14 | alpha << 28 | version << 29;
+            let mut data = vec![0x2f];
+            data.extend_from_slice(&bits.to_le_bytes());
+            WebPDecoder::new(Cursor::new(webp(&[chunk(b"VP8L", &data)])))
+        };
+
+        assert_eq!(header(1, 1, 0, 0).unwrap().dimensions(), (1, 1));
+        assert_eq!(header(16384, 3, 1, 0).unwrap().dimensions(), (16384, 3));
+        assert_eq!(header(5, 16384, 1, 0).unwrap().dimensions(), (5, 16384));
+        assert!(matches!(
+            header(1, 1, 0, 1),
+            Err(DecodingError::VersionNumberInvalid(1))
+        ));
+    }
+
+    #[test]
+    fn decode_to_writer() {
+        /// Records the largest single write
+        #[derive(Default)]
+        struct Rows {
+            data: Vec<u8>,
+            largest_write: usize,
+        }
+        impl Write for Rows {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.largest_write = self.largest_write.max(buf.len());
+                self.data.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        for name in ["1", "2_webp_a", "1_webp_ll", "2_webp_ll"] {
+            let bytes = std::fs::read(format!("tests/images/{name}.webp")).unwrap();
+            let mut decoder = new_decoder(bytes);
+
+            let mut expected = vec![0; decoder.output_buffer_size()];
+            decoder.read_image(&mut expected).unwrap();
+
+            let mut rows = Rows::default();
+            decoder.decode_to_writer(&mut rows).unwrap();
+            assert!(rows.data == expected, "{name}");
+            let row_bytes = expected.len() / decoder.dimensions().1 as usize;
+            assert_eq!(rows.largest_write, row_bytes, "{name}");
+        }
+    }
+
+    #[test]
+    fn from_bytes() {
+        let bytes = std::fs::read("tests/images/4_webp_a.webp").unwrap();
+        let mut decoder = WebPDecoder::from_bytes(&bytes).unwrap();
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        decoder.read_image(&mut buf).unwrap();
+
+        let mut reference = new_decoder(bytes.clone());
+        let mut expected = vec![0; reference.output_buffer_size()];
+        reference.read_image(&mut expected).unwrap();
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn new_at_offset() {
+        let decode_still = |bytes: Vec<u8>, offset: u64| {
+            let mut decoder = WebPDecoder::new_at_offset(Cursor::new(bytes), offset).unwrap();
+            let mut buf = vec![0; decoder.output_buffer_size()];
+            decoder.read_image(&mut buf).unwrap();
+            buf
+        };
+
+        let bytes = std::fs::read("tests/images/4_webp_a.webp").unwrap();
+        let mut embedded = b"leading bytes".to_vec();
+        embedded.extend_from_slice(&bytes);
+        assert_eq!(decode_still(embedded, 13), decode_still(bytes, 0));
+
+        let animation = solid_animation(1, 1, [0; 4], &[(0, 0, 1, 1, 10, 0, [1, 2, 3, 4])]);
+        let mut embedded = vec![0xff; 7];
+        embedded.extend_from_slice(&animation);
+        let mut decoder = WebPDecoder::new_at_offset(Cursor::new(embedded), 7).unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(10));
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn alpha_chunk_size_mismatch() {
+        for actual in [11, 13] {
+            let mut data = vec![0];
+            data.resize(1 + actual, 255);
+            let result = extended::read_alpha_chunk(
+                &mut Cursor::new(data),
+                4,
+                3,
+                HuffmanStrategy::default(),
+                usize::MAX,
+            );
+            match result {
+                Err(DecodingError::AlphaChunkSizeMismatch {
+                    expected: 12,
+                    actual: a,
+                }) => assert_eq!(a, actual),
+                Err(e) => panic!("unexpected error {e:?}"),
+                Ok(_) => panic!("{actual} alpha bytes were accepted"),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_alpha_header() {
+        for (info_byte, field) in [
+            (0b0100_0000, "reserved"),
+            (0b1000_0000, "reserved"),
+            (0b0010_0000, "preprocessing"),
+            (0b0011_0000, "preprocessing"),
+            (0b0000_0010, "lossless compression"),
+            (0b0000_0011, "lossless compression"),
+        ] {
+            let mut data = vec![info_byte];
+            data.resize(1 + 12, 255);
+            let result = extended::read_alpha_chunk(
+                &mut Cursor::new(data),
+                4,
+                3,
+                HuffmanStrategy::default(),
+                usize::MAX,
+            );
+            match result {
+                Err(DecodingError::InfoBitsInvalid { name, .. }) => assert_eq!(name, field),
+                Err(e) => panic!("unexpected error {e:?}"),
+                Ok(_) => panic!("alpha header {info_byte:#010b} was accepted"),
+            }
+        }
+    }
+
+    #[test]
+    fn vp8_frame_header() {
+        let bytes = std::fs::read("tests/images/1.webp").unwrap();
+        let header = new_decoder(bytes).vp8_frame_header().unwrap().unwrap();
+        assert_eq!(
+            header,
+            Vp8Header {
+                profile: 1,
+                filter_type: FilterType::Simple,
+                filter_level: 4,
+                segmentation_enabled: true,
+                width: 550,
+                height: 368,
+            }
+        );
+
+        let bytes = std::fs::read("tests/images/4_webp_ll.webp").unwrap();
+        assert_eq!(new_decoder(bytes).vp8_frame_header().unwrap(), None);
+    }
+
+    #[test]
+    fn alpha_encoding_info() {
+        let bytes = std::fs::read("tests/images/4_webp_a.webp").unwrap();
+        let info = new_decoder(bytes).alpha_encoding_info().unwrap();
+        assert_eq!(
+            info,
+            Some(AlphaEncodingInfo {
+                filtering_method: 0,
+                compression_method: 1,
+                preprocessing: 0,
+            })
+        );
+
+        let (vp8, width, height) = lossy_chunk();
+        let alpha_image = |info_byte| {
+            webp(&[
+                vp8x(VP8X_ALPHA, width, height),
+                chunk(b"ALPH", &[info_byte]),
+                vp8.clone(),
+            ])
+        };
+        let info = new_decoder(alpha_image(0b0001_1001))
+            .alpha_encoding_info()
+            .unwrap();
+        assert_eq!(
+            info,
+            Some(AlphaEncodingInfo {
+                filtering_method: 2,
+                compression_method: 1,
+                preprocessing: 1,
+            })
+        );
+        assert!(new_decoder(alpha_image(0b0100_0000))
+            .alpha_encoding_info()
+            .is_err());
+
+        let bytes = std::fs::read("tests/images/4_webp_ll.webp").unwrap();
+        assert_eq!(new_decoder(bytes).alpha_encoding_info().unwrap(), None);
+    }
+
+    #[test]
+    fn odd_sized_chunks() {
+        // Trailing data after a VP8L bitstream is ignored, so it can be padded to an odd size.
+        let mut frame = solid_color_image(3, 2, [1, 2, 3, 4]);
+        if frame.len() % 2 == 0 {
+            frame.push(0);
+        }
+        let mut bytes = webp(&[
+            vp8x(VP8X_ALPHA | 0b1100, 3, 2),
+            chunk(b"EXIF", &[1, 2, 3]),
+            chunk(b"VP8L", &frame),
+            chunk(b"XMP ", &[4, 5, 6, 7, 8]),
+        ]);
+        // Chunks past the end of the RIFF chunk are not part of the file.
+        bytes.extend_from_slice(&chunk(b"ICCP", &[9]));
+
+        let mut decoder = new_decoder(bytes);
+        assert_eq!(decoder.exif_metadata().unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(decoder.xmp_metadata().unwrap(), Some(vec![4, 5, 6, 7, 8]));
+        assert_eq!(decoder.icc_profile().unwrap(), None);
+
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        decoder.read_image(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4].repeat(6));
+    }
+
+    #[test]
+    fn output_format() {
+        let still = |file: &str| {
+            let bytes = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+            new_decoder(bytes).output_format()
+        };
+        assert_eq!(still("1"), PixelFormat::Rgb8);
+        assert_eq!(still("1_webp_ll"), PixelFormat::Rgba8);
+        assert_eq!(still("1_webp_a"), PixelFormat::Rgba8);
+    }
+
+    #[test]
+    fn empty_icc_profile() {
+        let icc_flag = 0b00100000;
+        let image = |icc: &[u8]| {
+            webp(&[
+                vp8x(icc_flag, 1, 1),
+                chunk(b"ICCP", icc),
+                chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 255])),
+            ])
+        };
+
+        let mut decoder = new_decoder(image(&[]));
+        assert!(!decoder.has_icc_profile());
+        assert_eq!(decoder.icc_profile().unwrap(), None);
+        assert_eq!(decoder.color_space(), ColorSpace::Srgb);
+
+        let mut decoder = new_decoder(image(&[1, 2]));
+        assert!(decoder.has_icc_profile());
+        assert_eq!(decoder.icc_profile().unwrap(), Some(vec![1, 2]));
+        assert_eq!(decoder.color_space(), ColorSpace::IccProfile);
+
+        let bytes = std::fs::read("tests/images/4_webp_ll.webp").unwrap();
+        assert!(!new_decoder(bytes).has_icc_profile());
+    }
+
+    #[test]
+    fn has_metadata() {
+        let vp8l = chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 255]));
+        let bytes = std::fs::read("tests/images/4_webp_ll.webp").unwrap();
+        let decoder = new_decoder(bytes);
+        assert!(!decoder.has_exif());
+        assert!(!decoder.has_xmp());
+
+        let decoder = new_decoder(webp(&[
+            vp8x(VP8X_EXIF, 1, 1),
+            vp8l.clone(),
+            chunk(b"EXIF", &[1]),
+        ]));
+        assert!(decoder.has_exif());
+        assert!(!decoder.has_xmp());
+
+        let decoder = new_decoder(webp(&[
+            vp8x(VP8X_ANIMATION | VP8X_EXIF | 0b100, 1, 1),
+            anim([0; 4], 0),
+            anmf(0, 0, 1, 1, 10, 0, &[vp8l]),
+            chunk(b"EXIF", &[1]),
+            chunk(b"XMP ", &[2]),
+        ]));
+        assert!(decoder.has_exif());
+        assert!(decoder.has_xmp());
+    }
+
+    #[test]
+    fn metadata_limit() {
+        let bytes = webp(&[
+            vp8x(VP8X_EXIF | 0b100, 1, 1),
+            chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 255])),
+            chunk(b"EXIF", &[1, 2, 3]),
+            chunk(b"XMP ", &[4, 5, 6, 7]),
+        ]);
+        let mut decoder = new_decoder(bytes);
+        decoder.set_memory_limit(2);
+        assert!(matches!(
+            decoder.exif_metadata(),
+            Err(DecodingError::MemoryLimitExceeded)
+        ));
+
+        decoder.set_metadata_limit(3);
+        assert_eq!(decoder.exif_metadata().unwrap(), Some(vec![1, 2, 3]));
+        assert!(matches!(
+            decoder.xmp_metadata(),
+            Err(DecodingError::MemoryLimitExceeded)
+        ));
+
+        // The metadata limit is kept when the memory limit changes.
+        decoder.set_memory_limit(usize::MAX);
+        assert!(matches!(
+            decoder.xmp_metadata(),
+            Err(DecodingError::MemoryLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn raw_chunk() {
+        let image = solid_color_image(1, 1, [1, 2, 3, 255]);
+        let bytes = webp(&[
+            vp8x(VP8X_EXIF | 0b100000, 1, 1),
+            chunk(b"ICCP", &[]),
+            chunk(b"VP8L", &image),
+            chunk(b"EXIF", &[4, 5]),
+            chunk(b"DPTH", &[6]),
+        ]);
+        let mut decoder = new_decoder(bytes);
+        assert_eq!(decoder.raw_chunk(ChunkType::VP8L).unwrap(), Some(image));
+        assert_eq!(decoder.raw_chunk(ChunkType::ICCP).unwrap(), Some(vec![]));
+        assert_eq!(
+            decoder.raw_chunk(ChunkType::EXIF).unwrap(),
+            Some(vec![4, 5])
+        );
+        assert_eq!(
+            decoder.raw_chunk(ChunkType::Unknown(*b"DPTH")).unwrap(),
+            Some(vec![6])
+        );
+        assert_eq!(decoder.raw_chunk(ChunkType::XMP).unwrap(), None);
+
+        decoder.set_metadata_limit(1);
+        assert!(matches!(
+            decoder.raw_chunk(ChunkType::EXIF),
+            Err(DecodingError::MemoryLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn lossless_transforms() {
+        let bytes = std::fs::read("tests/images/1_webp_ll.webp").unwrap();
+        assert_eq!(
+            new_decoder(bytes).lossless_transforms().unwrap(),
+            [
+                LosslessTransform::SubtractGreen,
+                LosslessTransform::Predictor { size_bits: 3 },
+                LosslessTransform::Color { size_bits: 3 },
+            ]
+        );
+
+        let bytes = webp(&[chunk(b"VP8L", &solid_color_image(2, 2, [1, 2, 3, 4]))]);
+        assert!(new_decoder(bytes).lossless_transforms().unwrap().is_empty());
+
+        let bytes = std::fs::read("tests/images/1.webp").unwrap();
+        assert!(new_decoder(bytes).lossless_transforms().unwrap().is_empty());
+    }
+
+    #[test]
+    fn decode_with_metadata() {
+        let bytes = webp(&[
+            vp8x(0b101000, 1, 1),
+            chunk(b"ICCP", &[1, 2]),
+            chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 255])),
+            chunk(b"EXIF", &[3]),
+        ]);
+        let image = new_decoder(bytes).decode_with_metadata().unwrap();
+        assert_eq!(
+            image,
+            DecodedImage {
+                pixels: vec![1, 2, 3],
+                width: 1,
+                height: 1,
+                format: PixelFormat::Rgb8,
+                icc: Some(vec![1, 2]),
+                exif: Some(vec![3]),
+                xmp: None,
+            }
+        );
+    }
+
+    #[test]
+    fn lossless_without_alpha_flag() {
+        let bytes = webp(&[
+            vp8x(0, 2, 1),
+            chunk(b"VP8L", &solid_color_image(2, 1, [1, 2, 3, 4])),
+        ]);
+        let mut decoder = new_decoder(bytes);
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        decoder.read_image(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn animation_without_alpha() {
+        for num_frames in [1, 2] {
+            let mut chunks = vec![vp8x(VP8X_ANIMATION, 2, 1), anim([0; 4], 0)];
+            for i in 0..num_frames {
+                let frame = solid_color_image(2, 1, [i, 2, 3, 255]);
+                chunks.push(anmf(0, 0, 2, 1, 10, 0b10, &[chunk(b"VP8L", &frame)]));
+            }
+
+            let mut decoder = new_decoder(webp(&chunks));
+            assert_eq!(decoder.output_format(), PixelFormat::Rgb8);
+            let mut buf = vec![0; decoder.output_buffer_size()];
+            for i in 0..num_frames {
+                assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(10));
+                assert_eq!(buf, [i, 2, 3, i, 2, 3]);
+            }
+        }
+    }
+
+    #[test]
+    fn strided_rows() {
+        let bytes = std::fs::read("tests/images/2_webp_ll.webp").unwrap();
+        let mut decoder = new_decoder(bytes);
+        let mut full = vec![0; decoder.output_buffer_size()];
+        decoder.read_image(&mut full).unwrap();
+        let row_bytes = full.len() / decoder.dimensions().1 as usize;
+
+        let mut buf = vec![0; full.len()];
+        decoder.read_image_strided_rows(&mut buf, 2, 0).unwrap();
+        for (y, (row, full_row)) in buf
+            .chunks_exact(row_bytes)
+            .zip(full.chunks_exact(row_bytes))
+            .enumerate()
+        {
+            if y % 2 == 0 {
+                assert_eq!(row, full_row);
+            } else {
+                assert!(row.iter().all(|&b| b == 0));
+            }
+        }
+
+        decoder.read_image_strided_rows(&mut buf, 2, 1).unwrap();
+        assert_eq!(buf, full);
+
+        assert!(decoder.read_image_strided_rows(&mut buf, 0, 0).is_err());
+        assert!(decoder.read_image_strided_rows(&mut buf, 2, 2).is_err());
+    }
+
+    #[test]
+    fn image_rows() {
+        for file in ["2_webp_ll", "2_webp_a"] {
+            let bytes = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+            let mut decoder = new_decoder(bytes);
+            let mut full = vec![0; decoder.output_buffer_size()];
+            decoder.read_image(&mut full).unwrap();
+            let height = decoder.dimensions().1;
+            let row_bytes = full.len() / height as usize;
+
+            let mut band = vec![0; 10 * row_bytes];
+            decoder.read_image_rows(5, 10, &mut band).unwrap();
+            assert_eq!(band, full[5 * row_bytes..15 * row_bytes]);
+
+            let mut last = vec![0; row_bytes];
+            decoder.read_image_rows(height - 1, 1, &mut last).unwrap();
+            assert_eq!(last, full[full.len() - row_bytes..]);
+
+            assert!(decoder.read_image_rows(height, 1, &mut last).is_err());
+            assert!(decoder.read_image_rows(u32::MAX, 1, &mut last).is_err());
+        }
+    }
+
+    #[test]
+    fn progress_callback() {
+        let bytes = solid_animation(2, 1, [0; 4], &[(0, 0, 2, 1, 10, 0, [1, 2, 3, 255]); 2]);
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut decoder = new_decoder(bytes.clone());
+        let recorded = events.clone();
+        decoder.set_progress_callback(Box::new(move |event| recorded.lock().unwrap().push(event)));
+
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        decoder.read_frame(&mut buf).unwrap();
+        decoder.read_frame(&mut buf).unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 4);
+        assert!(matches!(
+            events[0],
+            ProgressEvent::Bytes { bytes_read, total_bytes } if bytes_read < total_bytes
+        ));
+        assert_eq!(
+            events[1],
+            ProgressEvent::Frame {
+                frame_index: 0,
+                num_frames: 2
+            }
+        );
+        assert_eq!(
+            events[2],
+            ProgressEvent::Bytes {
+                bytes_read: bytes.len() as u64,
+                total_bytes: bytes.len() as u64
+            }
+        );
+        assert_eq!(
+            events[3],
+            ProgressEvent::Frame {
+                frame_index: 1,
+                num_frames: 2
+            }
+        );
+    }
+
+    #[test]
+    fn reused_vp8_buffers() {
+        let (vp8, width, height) = lossy_chunk();
+        let frame = anmf(0, 0, width, height, 10, 0b10, &[vp8]);
+        let bytes = webp(&[
+            vp8x(VP8X_ANIMATION, width, height),
+            anim([0; 4], 0),
+            frame.clone(),
+            frame,
+        ]);
+
+        let mut decoder = new_decoder(bytes);
+        let mut first = vec![0; decoder.output_buffer_size()];
+        let mut second = vec![0; decoder.output_buffer_size()];
+        decoder.read_frame(&mut first).unwrap();
+        decoder.read_frame(&mut second).unwrap();
+        assert!(first == second);
+    }
+
+    #[cfg(feature = "benchmarks")]
+    #[bench]
+    fn bench_lossy_animation(b: &mut test::Bencher) {
+        let (vp8, width, height) = lossy_chunk();
+        let frame = anmf(0, 0, width, height, 10, 0b10, &[vp8]);
+        let mut chunks = vec![vp8x(VP8X_ANIMATION, width, height), anim([0; 4], 0)];
+        chunks.extend(std::iter::repeat(frame).take(300));
+        let mut decoder = new_decoder(webp(&chunks));
+        let mut buf = vec![0; decoder.output_buffer_size()];
+
+        b.iter(|| decoder.read_frame(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn upscaled_vp8_frame() {
+        let (mut vp8, width, height) = lossy_chunk();
+        // Request 2x horizontal upscaling, with the frame rectangle covering the upscaled size.
+        vp8[15] |= 0b1100_0000;
+        let bytes = webp(&[
+            vp8x(VP8X_ANIMATION, 2 * width, height),
+            anim([0; 4], 0),
+            anmf(0, 0, 2 * width, height, 100, 0, &[vp8]),
+        ]);
+
+        let mut decoder = new_decoder(bytes);
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        match decoder.read_frame(&mut buf) {
+            Err(DecodingError::UnsupportedFeature(feature)) => {
+                assert_eq!(feature, "VP8 frame upscaling")
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[test]
+    fn frames() {
+        let mut chunks = vec![vp8x(VP8X_ALPHA | VP8X_ANIMATION, 2, 1), anim([0; 4], 1)];
+        for (duration, rgba) in [(10, [255, 0, 0, 255]), (20, [0, 0, 255, 255])] {
+            let frame = solid_color_image(2, 1, rgba);
+            chunks.push(anmf(0, 0, 2, 1, duration, 0, &[chunk(b"VP8L", &frame)]));
+        }
+        let mut decoder = new_decoder(webp(&chunks));
+
+        let frames = decoder.frames().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            frames,
+            [
+                ([255, 0, 0, 255, 255, 0, 0, 255].to_vec(), 10),
+                ([0, 0, 255, 255, 0, 0, 255, 255].to_vec(), 20),
+            ]
+        );
+
+        let still = webp(&[chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 4]))]);
+        let frames = new_decoder(still).frames().collect::<Result<Vec<_>, _>>();
+        assert_eq!(frames.unwrap(), [(vec![1, 2, 3, 4], 0)]);
+    }
+
+    #[test]
+    fn chunks() {
+        let image = solid_color_image(1, 1, [0; 4]);
+        let bytes = webp(&[
+            vp8x(VP8X_EXIF, 1, 1),
+            chunk(b"VP8L", &image),
+            chunk(b"EXIF", &[0; 5]),
+        ]);
+        let chunks: Vec<_> = new_decoder(bytes).chunks().collect();
+        assert_eq!(
+            chunks,
+            [
+                (ChunkType::VP8X, 10),
+                (ChunkType::VP8L, image.len() as u64),
+                (ChunkType::EXIF, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn non_keyframe() {
+        let (mut vp8, _, _) = lossy_chunk();
+        vp8[8] |= 1;
+        assert!(matches!(
+            WebPDecoder::new(Cursor::new(webp(&[vp8]))),
+            Err(DecodingError::NonKeyframe)
+        ));
+    }
+
+    #[test]
+    fn trailing_data() {
+        let image = webp(&[
+            vp8x(VP8X_EXIF, 1, 1),
+            chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 4])),
+            chunk(b"EXIF", &[5; 6]),
+        ]);
+        let mut bytes = image.clone();
+        bytes.extend_from_slice(b"appended");
+        assert_eq!(
+            new_decoder(bytes.clone()).trailing_data_offset(),
+            image.len() as u64
+        );
+
+        // A RIFF size that is too small to cover the last chunk.
+        bytes[4..8].copy_from_slice(&(image.len() as u32 - 12).to_le_bytes());
+        let mut decoder = new_decoder(bytes);
+        assert_eq!(decoder.trailing_data_offset(), image.len() as u64);
+        assert_eq!(decoder.exif_metadata().unwrap(), Some(vec![5; 6]));
+
+        let simple = webp(&[chunk(b"VP8L", &solid_color_image(1, 1, [0; 4]))]);
+        let mut truncated = simple.clone();
+        truncated.truncate(simple.len() - 1);
+        assert_eq!(
+            new_decoder(truncated).trailing_data_offset(),
+            simple.len() as u64 - 1
+        );
+    }
+
+    #[test]
+    fn unknown_chunks() {
+        let bytes = webp(&[
+            vp8x(VP8X_EXIF, 1, 1),
+            chunk(b"VP8L", &solid_color_image(1, 1, [0; 4])),
+            chunk(b"DPTH", &[1, 2, 3]),
+            chunk(b"EXIF", &[4, 5]),
+        ]);
+        let mut decoder = new_decoder(bytes);
+        assert_eq!(decoder.exif_metadata().unwrap(), Some(vec![4, 5]));
+        assert_eq!(
+            decoder.read_unknown_chunk(*b"DPTH").unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(decoder.read_unknown_chunk(*b"GAIN").unwrap(), None);
+
+        decoder.set_memory_limit(2);
+        assert!(matches!(
+            decoder.read_unknown_chunk(*b"DPTH"),
+            Err(DecodingError::MemoryLimitExceeded)
+        ));
+
+        // Unknown chunks between frames are skipped.
+        let frame = |rgba| {
+            let image = solid_color_image(1, 1, rgba);
+            anmf(0, 0, 1, 1, 100, 0b10, &[chunk(b"VP8L", &image)])
+        };
+        let bytes = webp(&[
+            vp8x(VP8X_ALPHA | VP8X_ANIMATION, 1, 1),
+            anim([0; 4], 1),
+            frame([1, 2, 3, 4]),
+            chunk(b"DPTH", &[1, 2, 3]),
+            frame([5, 6, 7, 8]),
+        ]);
+        let mut decoder = new_decoder(bytes);
+        assert_eq!(decoder.frame_offsets().unwrap().len(), 2);
+        let frames: Vec<_> = decoder.frames().map(|frame| frame.unwrap().0).collect();
+        assert_eq!(frames, [[1, 2, 3, 4], [5, 6, 7, 8]]);
+    }
+
+    #[test]
+    fn verify_structure() {
+        for name in ["1.webp", "2_webp_a.webp", "3_webp_ll.webp"] {
+            let bytes = std::fs::read(format!("tests/images/{name}")).unwrap();
+            new_decoder(bytes).verify_structure().unwrap();
+        }
+
+        let bytes = webp(&[
+            vp8x(VP8X_EXIF, 1, 1),
+            chunk(b"VP8L", &solid_color_image(1, 1, [0; 4])),
+            chunk(b"EXIF", &[1]),
+        ]);
+        new_decoder(bytes.clone()).verify_structure().unwrap();
+
+        let mut prefixed = vec![0; 3];
+        prefixed.extend_from_slice(&bytes);
+        let mut decoder = WebPDecoder::new_at_offset(Cursor::new(prefixed), 3).unwrap();
+        decoder.verify_structure().unwrap();
+
+        let with_riff_size = |riff_size: u32, extra: usize| {
+            let mut bytes = bytes.clone();
+            bytes[4..8].copy_from_slice(&riff_size.to_le_bytes());
+            bytes.resize(bytes.len() + extra, 0);
+            bytes
+        };
+        let riff_size = bytes.len() as u32 - 8;
+        for (riff_size, extra) in [
+            // Unused space at the end of the payload
+            (riff_size + 2, 2),
+            (riff_size + 12, 12),
+            // The last chunk extends past the payload
+            (riff_size - 2, 0),
+            // The stream ends before the payload
+            (riff_size + 2, 0),
+        ] {
+            let mut decoder = new_decoder(with_riff_size(riff_size, extra));
+            assert!(matches!(
+                decoder.verify_structure(),
+                Err(DecodingError::InvalidChunkSize)
+            ));
+        }
+    }
+
+    #[test]
+    fn structure_report() {
+        let iccp = chunk(b"ICCP", &[0; 3]);
+        let image = solid_color_image(2, 3, [1, 2, 3, 4]);
+        let vp8l = chunk(b"VP8L", &image);
+        let report = new_decoder(webp(&[vp8x(VP8X_ALPHA, 2, 3), iccp, vp8l])).structure_report();
+
+        assert_eq!((report.width, report.height), (2, 3));
+        assert_eq!(report.class, WebPClass::Extended);
+        let chunks: Vec<_> = report
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.fourcc.as_str(), chunk.offset, chunk.size))
+            .collect();
+        assert_eq!(
+            chunks,
+            [
+                ("VP8X", 12, 10),
+                ("ICCP", 30, 3),
+                ("VP8L", 42, image.len() as u64)
+            ]
+        );
+        assert_eq!(report.loop_count, None);
+        assert_eq!(
+            report.warnings,
+            ["ICC profile chunk present without its VP8X flag"]
+        );
+
+        let frame = (0, 0, 1, 1, 100, 0, [255, 0, 0, 255]);
+        let report =
+            new_decoder(solid_animation(1, 1, [9, 8, 7, 6], &[frame; 2])).structure_report();
+        assert_eq!(report.num_frames, 2);
+        assert_eq!(report.loop_count, Some(LoopCount::Forever));
+        // The ANIM chunk stores the color as BGRA.
+        assert_eq!(report.background_color, Some([7, 8, 9, 6]));
+        assert!(report.warnings.is_empty());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn decode_all_frames() {
+        let frames = [
+            (0, 0, 2, 2, 10, 0, [255, 0, 0, 255]),
+            (0, 0, 1, 1, 20, 0b11, [0, 255, 0, 128]),
+            (1, 1, 1, 1, 30, 0, [0, 0, 255, 255]),
+        ];
+        let bytes = solid_animation(2, 2, [0; 4], &frames);
+        let mut decoder = new_decoder(bytes.clone());
+        let decoded = decoder.decode_all_frames().unwrap();
+
+        let mut reference = new_decoder(bytes);
+        let mut buf = vec![0; reference.output_buffer_size()];
+        for (data, duration) in &decoded {
+            assert_eq!(reference.read_frame(&mut buf).unwrap(), Some(*duration));
+            assert_eq!(&buf, data);
+        }
+
+        decoder.read_frame(&mut buf).unwrap();
+        assert_eq!(buf, decoded[0].0);
+
+        let (vp8, width, height) = lossy_chunk();
+        let mut alpha = vec![0];
+        alpha.resize(1 + (width * height) as usize, 128);
+        let bytes = webp(&[
+            vp8x(VP8X_ANIMATION | VP8X_ALPHA, width, height),
+            anim([0; 4], 0),
+            anmf(
+                0,
+                0,
+                width,
+                height,
+                10,
+                0b10,
+                &[chunk(b"ALPH", &alpha), vp8],
+            ),
+        ]);
+        let decoded = new_decoder(bytes.clone()).decode_all_frames().unwrap();
+        let mut reference = new_decoder(bytes);
+        let mut buf = vec![0; reference.output_buffer_size()];
+        reference.read_frame(&mut buf).unwrap();
+        assert_eq!(decoded, [(buf, 10)]);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn decode_all_frames_limits() {
+        let frame = (0, 0, 1, 1, 10, 0, [255, 0, 0, 255]);
+        let mut decoder = new_decoder(solid_animation(1, 1, [0; 4], &[frame, frame]));
+        decoder.set_memory_limit(7);
+        assert!(matches!(
+            decoder.decode_all_frames(),
+            Err(DecodingError::MemoryLimitExceeded)
+        ));
+
+        // The ANMF and VP8L sizes of the second frame claim about 4 GiB, far past the end of the
+        // file, which must fail before allocating anything for it.
+        let mut bytes = solid_animation(1, 1, [0; 4], &[frame]);
+        let mut anmf = b"ANMF".to_vec();
+        anmf.extend_from_slice(&0xffff_fff0u32.to_le_bytes());
+        anmf.extend_from_slice(&[0; 16]);
+        anmf.extend_from_slice(b"VP8L");
+        anmf.extend_from_slice(&0xffff_ff00u32.to_le_bytes());
+        anmf.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&anmf);
+        let riff_size = bytes.len() as u32 - 8;
+        bytes[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+        let mut decoder = new_decoder(bytes);
+        assert_eq!(decoder.num_frames(), 2);
+        match decoder.decode_all_frames() {
+            Err(DecodingError::IoError(e)) => assert_eq!(e.to_string(), "Frame data is truncated"),
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[test]
+    fn vp8x_reserved_bits() {
+        let vp8l = chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 255]));
+        let mut headers = Vec::new();
+        for flags in [0b1000_0000, 0b0100_0000, 0b0000_0001] {
+            headers.push(vp8x(flags, 1, 1));
+        }
+        for byte in 9..12 {
+            let mut header = vp8x(0, 1, 1);
+            header[byte] = 1;
+            headers.push(header);
+        }
+
+        for header in headers {
+            let result = WebPDecoder::new(Cursor::new(webp(&[header.clone(), vp8l.clone()])));
+            assert!(
+                matches!(
+                    result,
+                    Err(DecodingError::InfoBitsInvalid {
+                        name: "reserved",
+                        ..
+                    })
+                ),
+                "{header:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn frame_too_large() {
+        let vp8l = chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 255]));
+        let bytes = webp(&[
+            vp8x(VP8X_ANIMATION, 20000, 1),
+            anim([0; 4], 0),
+            anmf(0, 0, 20000, 1, 100, 0, &[vp8l]),
+        ]);
+        let mut decoder = new_decoder(bytes);
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        assert!(matches!(
+            decoder.read_frame(&mut buf),
+            Err(DecodingError::FrameTooLarge {
+                width: 20000,
+                height: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn huge_subchunk_sizes() {
+        let (vp8, width, height) = lossy_chunk();
+        for fourcc in [b"ALPH", b"VP8 ", b"VP8L"] {
+            for size in [u32::MAX - 40, u32::MAX - 1, u32::MAX] {
+                let mut subchunk = fourcc.to_vec();
+                subchunk.extend_from_slice(&size.to_le_bytes());
+                let bytes = webp(&[
+                    vp8x(VP8X_ANIMATION, width, height),
+                    anim([0; 4], 0),
+                    anmf(0, 0, width, height, 100, 0, std::slice::from_ref(&vp8)),
+                    anmf(0, 0, width, height, 100, 0, &[subchunk, vp8.clone()]),
+                ]);
+                let mut decoder = new_decoder(bytes);
+                let mut buf = vec![0; decoder.output_buffer_size()];
+                decoder.read_frame(&mut buf).unwrap();
+                assert!(matches!(
+                    decoder.read_frame(&mut buf),
+                    Err(DecodingError::ChunkHeaderInvalid(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn size_policy() {
+        // The VP8X canvas is too small for the second frame.
+        let frame = (0, 0, 1, 1, 100, 0, [255, 0, 0, 255]);
+        let mut bytes = solid_animation(1, 1, [0; 4], &[frame, (2, 0, 3, 2, 100, 0, [0; 4])]);
+        let mut decoder = new_decoder(bytes.clone());
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        decoder.read_frame(&mut buf).unwrap();
+        assert!(matches!(
+            decoder.read_frame(&mut buf),
+            Err(DecodingError::FrameOutsideImage)
+        ));
+
+        decoder.set_size_policy(SizePolicy::UseFrameSize).unwrap();
+        assert_eq!(decoder.dimensions(), (5, 2));
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(100));
+        assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(100));
+
+        decoder.set_size_policy(SizePolicy::UseCanvasSize).unwrap();
+        assert_eq!(decoder.dimensions(), (1, 1));
+
+        // A still image whose canvas is larger than its bitstream.
+        bytes = webp(&[
+            vp8x(VP8X_ALPHA, 4, 4),
+            chunk(b"VP8L", &solid_color_image(2, 3, [1, 2, 3, 4])),
+        ]);
+        let mut decoder = new_decoder(bytes);
+        decoder.set_size_policy(SizePolicy::UseFrameSize).unwrap();
+        assert_eq!(decoder.dimensions(), (2, 3));
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        decoder.read_image(&mut buf).unwrap();
+        assert_eq!(&buf[..4], [1, 2, 3, 4]);
+
+        // A frame far outside the canvas would need a canvas larger than 4 GiB.
+        let vp8l = chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 4]));
+        let far = (1 << 25) - 2;
+        bytes = webp(&[
+            vp8x(VP8X_ANIMATION, 1, 1),
+            anim([0; 4], 0),
+            anmf(0, 0, 1, 1, 100, 0, std::slice::from_ref(&vp8l)),
+            anmf(far, far, 1, 1, 100, 0, &[vp8l]),
+        ]);
+        let mut decoder = new_decoder(bytes);
+        assert!(matches!(
+            decoder.set_size_policy(SizePolicy::UseFrameSize),
+            Err(DecodingError::ImageTooLarge)
+        ));
+        assert_eq!(decoder.dimensions(), (1, 1));
+
+        // The bitstream size is within the memory limit of the VP8X canvas, but not its own.
+        let mut decoder = new_decoder(webp(&[
+            vp8x(VP8X_ALPHA, 1, 1),
+            chunk(b"VP8L", &solid_color_image(2, 3, [1, 2, 3, 4])),
+        ]));
+        decoder.set_memory_limit(4);
+        assert!(matches!(
+            decoder.set_size_policy(SizePolicy::UseFrameSize),
+            Err(DecodingError::ImageTooLarge)
+        ));
+
+        // VP8 does not store its dimensions minus one, so they can be zero.
+        let (mut vp8, width, height) = lossy_chunk();
+        vp8[14..16].copy_from_slice(&[0, 0]);
+        let mut decoder = new_decoder(webp(&[vp8x(0, width, height), vp8]));
+        assert!(matches!(
+            decoder.set_size_policy(SizePolicy::UseFrameSize),
+            Err(DecodingError::InfoBitsInvalid {
+                name: "canvas_size",
+                value: 0
+            })
+        ));
+        assert_eq!(decoder.dimensions(), (width, height));
+    }
+
+    #[test]
+    fn frame_info() {
+        let frames = [
+            (0, 0, 2, 2, 10, 0, [255, 0, 0, 255]),
+            (2, 4, 1, 3, 20, 0b11, [0, 255, 0, 255]),
+        ];
+        let mut decoder = new_decoder(solid_animation(4, 8, [0; 4], &frames));
+
+        assert_eq!(
+            decoder.frame_info(1).unwrap(),
+            FrameInfo {
+                x: 2,
+                y: 4,
+                width: 1,
+                height: 3,
+                duration_ms: 20,
+                use_alpha_blending: false,
+                dispose: true,
+            }
+        );
+        let first = decoder.frame_info(0).unwrap();
+        assert!(first.use_alpha_blending && !first.dispose);
+        assert!(matches!(
+            decoder.frame_info(2),
+            Err(DecodingError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn read_frame_in_place() {
+        let frames = [
+            (0, 0, 2, 2, 10, 0, [255, 0, 0, 255]),
+            (0, 0, 1, 1, 20, 0, [0, 0, 255, 255]),
+        ];
+        let bytes = solid_animation(2, 2, [0; 4], &frames);
+
+        let mut decoder = new_decoder(bytes.clone());
+        let mut expected = vec![0; decoder.output_buffer_size()];
+        decoder.read_frame(&mut expected).unwrap();
+        decoder.read_frame(&mut expected).unwrap();
+
+        let mut decoder = new_decoder(bytes);
+        assert_eq!(decoder.canvas(), None);
+        assert_eq!(decoder.read_frame_in_place().unwrap(), Some(10));
+        assert_eq!(decoder.read_frame_in_place().unwrap(), Some(20));
+        assert_eq!(decoder.canvas(), Some(&expected[..]));
+    }
+
+    #[test]
+    fn read_alpha() {
+        for file in [
+            "1",
+            "1_webp_a",
+            "2_webp_a",
+            "3_webp_a",
+            "4_webp_a",
+            "5_webp_a",
+            "2_webp_ll",
+        ] {
+            let bytes = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+            let mut decoder = new_decoder(bytes);
+            let (width, height) = decoder.dimensions();
+            let mut alpha = vec![0; width as usize * height as usize];
+            decoder.read_alpha(&mut alpha).unwrap();
+
+            let mut image = vec![0; decoder.output_buffer_size()];
+            decoder.read_image(&mut image).unwrap();
+            if decoder.has_alpha() {
+                let expected: Vec<u8> = image.chunks_exact(4).map(|pixel| pixel[3]).collect();
+                assert_eq!(alpha, expected, "{file}");
+            } else {
+                assert!(alpha.iter().all(|&a| a == 255), "{file}");
+            }
+        }
 
-        Ok(Some(duration))
+        let bytes = solid_animation(
+            2,
+            1,
+            [0, 0, 0, 9],
+            &[
+                (0, 0, 1, 1, 10, 0b10, [1, 2, 3, 4]),
+                (0, 0, 2, 1, 20, 0b10, [1, 2, 3, 5]),
+            ],
+        );
+        let mut decoder = new_decoder(bytes);
+        let mut alpha = [0; 2];
+        decoder.read_alpha(&mut alpha).unwrap();
+        assert_eq!(alpha, [4, 9]);
+
+        // Playback is not affected, also after some frames were read.
+        let mut buf = [0; 8];
+        assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(10));
+        decoder.read_alpha(&mut alpha).unwrap();
+        assert_eq!(alpha, [4, 9]);
+        assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(20));
+        assert_eq!(buf, [1, 2, 3, 5, 1, 2, 3, 5]);
     }
-}
 
-pub(crate) fn range_reader<R: Read + Seek>(
-    mut r: R,
-    range: Range<u64>,
-) -> Result<impl Read, DecodingError> {
-    r.seek(io::SeekFrom::Start(range.start))?;
-    Ok(r.take(range.end - range.start))
-}
+    #[test]
+    fn read_image_premultiplied() {
+        let bytes = std::fs::read("tests/images/4_webp_a.webp").unwrap();
+        let mut decoder = new_decoder(bytes);
+        let mut straight = vec![0; decoder.output_buffer_size()];
+        decoder.read_image(&mut straight).unwrap();
+        let mut premultiplied = vec![0; decoder.output_buffer_size()];
+        decoder
+            .read_image_premultiplied(&mut premultiplied)
+            .unwrap();
 
-pub(crate) fn read_fourcc<R: Read>(mut r: R) -> Result<WebPRiffChunk, DecodingError> {
-    let mut chunk_fourcc = [0; 4];
-    r.read_exact(&mut chunk_fourcc)?;
-    Ok(WebPRiffChunk::from_fourcc(chunk_fourcc))
-}
+        for (s, p) in straight.chunks_exact(4).zip(premultiplied.chunks_exact(4)) {
+            let alpha = f32::from(s[3]);
+            for c in 0..3 {
+                assert_eq!(p[c], (f32::from(s[c]) * alpha / 255.0).round() as u8);
+            }
+            assert_eq!(p[3], s[3]);
+        }
+        assert!(straight.chunks_exact(4).any(|s| s[3] != 0 && s[3] != 255));
+    }
 
-pub(crate) fn read_chunk_header<R: Read>(
-    mut r: R,
-) -> Result<(WebPRiffChunk, u32, u32), DecodingError> {
-    let chunk = read_fourcc(&mut r)?;
-    let chunk_size = r.read_u32::<LittleEndian>()?;
-    let chunk_size_rounded = chunk_size.saturating_add(chunk_size & 1);
-    Ok((chunk, chunk_size, chunk_size_rounded))
-}
+    #[test]
+    fn orientation() {
+        let exif = |big_endian: bool, tags: &[(u16, u16, u16)]| {
+            let u16_bytes = |v: u16| {
+                if big_endian {
+                    v.to_be_bytes()
+                } else {
+                    v.to_le_bytes()
+                }
+            };
+            let mut data = if big_endian { b"MM\0*" } else { b"II*\0" }.to_vec();
+            data.extend_from_slice(&if big_endian {
+                8u32.to_be_bytes()
+            } else {
+                8u32.to_le_bytes()
+            });
+            data.extend_from_slice(&u16_bytes(tags.len() as u16));
+            for &(tag, kind, value) in tags {
+                data.extend_from_slice(&u16_bytes(tag));
+                data.extend_from_slice(&u16_bytes(kind));
+                data.extend_from_slice(&if big_endian {
+                    1u32.to_be_bytes()
+                } else {
+                    1u32.to_le_bytes()
+                });
+                data.extend_from_slice(&u16_bytes(value));
+                data.extend_from_slice(&[0, 0]);
+            }
+            data
+        };
+        let orientation = |exif_data: Option<Vec<u8>>| {
+            let flags = if exif_data.is_some() { VP8X_EXIF } else { 0 };
+            let mut chunks = vec![vp8x(flags, 1, 1)];
+            chunks.extend(exif_data.map(|data| chunk(b"EXIF", &data)));
+            chunks.push(chunk(b"VP8L", &solid_color_image(1, 1, [0; 4])));
+            new_decoder(webp(&chunks)).orientation().unwrap()
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let tags = [(0x010f, 2, 0), (0x0112, 3, 6)];
+        assert_eq!(
+            orientation(Some(exif(false, &tags))),
+            Some(Orientation::Rotate90)
+        );
+        assert_eq!(
+            orientation(Some(exif(true, &tags))),
+            Some(Orientation::Rotate90)
+        );
+        let mut prefixed = b"Exif\0\0".to_vec();
+        prefixed.extend(exif(true, &[(0x0112, 3, 3)]));
+        assert_eq!(orientation(Some(prefixed)), Some(Orientation::Rotate180));
+
+        assert_eq!(orientation(Some(exif(false, &[(0x010f, 2, 0)]))), None);
+        assert_eq!(orientation(Some(exif(false, &[(0x0112, 3, 9)]))), None);
+        assert_eq!(orientation(Some(b"garbage".to_vec())), None);
+        assert_eq!(orientation(None), None);
+    }
+
+    #[test]
+    fn read_image_luma() {
+        let bytes = webp(&[chunk(b"VP8L", &solid_color_image(2, 1, [255, 128, 0, 10]))]);
+        let mut decoder = new_decoder(bytes);
+        let mut buf = vec![0; decoder.output_buffer_size_luma()];
+        decoder.read_image_luma(&mut buf).unwrap();
+        assert_eq!(buf, [146, 146]);
+
+        // Solid black and white images agree between VP8L and VP8, which stores luma directly.
+        const BLACK_VP8: [u8; 46] = [
+            0x52, 0x49, 0x46, 0x46, 0x26, 0x00, 0x00, 0x00, 0x57, 0x45, 0x42, 0x50, 0x56, 0x50,
+            0x38, 0x20, 0x1a, 0x00, 0x00, 0x00, 0x30, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x10, 0x00,
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x25, 0xa4, 0x00, 0x03, 0x70, 0x00, 0xfe, 0xff, 0xdf,
+            0x6e, 0xd4, 0x00, 0x00,
+        ];
+        const WHITE_VP8: [u8; 46] = [
+            0x52, 0x49, 0x46, 0x46, 0x26, 0x00, 0x00, 0x00, 0x57, 0x45, 0x42, 0x50, 0x56, 0x50,
+            0x38, 0x20, 0x1a, 0x00, 0x00, 0x00, 0x30, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x10, 0x00,
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x25, 0xa4, 0x00, 0x03, 0x70, 0x00, 0xfe, 0xff, 0xde,
+            0xdc, 0xf0, 0x00, 0x00,
+        ];
+        for (vp8, rgba, luma) in [(BLACK_VP8, [0, 0, 0, 255], 16), (WHITE_VP8, [255; 4], 235)] {
+            let vp8l = webp(&[chunk(b"VP8L", &solid_color_image(16, 16, rgba))]);
+            let mut lossless = vec![0; 16 * 16];
+            new_decoder(vp8l).read_image_luma(&mut lossless).unwrap();
+            let mut lossy = vec![0; 16 * 16];
+            new_decoder(vp8.to_vec())
+                .read_image_luma(&mut lossy)
+                .unwrap();
+            assert_eq!(lossless, lossy);
+            assert_eq!(lossy, [luma; 16 * 16]);
+        }
+
+        let bytes = std::fs::read("tests/images/1.webp").unwrap();
+        let mut decoder = new_decoder(bytes.clone());
+        let mut buf = vec![0; decoder.output_buffer_size_luma()];
+        decoder.read_image_luma(&mut buf).unwrap();
+        let mut vp8 = Vp8Decoder::new(Cursor::new(&bytes[20..]));
+        assert_eq!(buf, vp8.decode_frame().unwrap().ybuf);
+    }
+
+    #[test]
+    fn read_image_u16() {
+        let bytes = webp(&[chunk(b"VP8L", &solid_color_image(1, 1, [0, 1, 128, 255]))]);
+        let mut decoder = new_decoder(bytes);
+        let mut buf = vec![0; decoder.output_buffer_size_u16()];
+        decoder.read_image_u16(&mut buf).unwrap();
+        assert_eq!(buf, [0, 0x0101, 0x8080, 0xffff]);
+    }
+
+    #[test]
+    fn pixel_layouts() {
+        let rgba = webp(&[chunk(b"VP8L", &solid_color_image(2, 1, [1, 2, 3, 4]))]);
+        let rgb = webp(&[
+            vp8x(0, 2, 1),
+            chunk(b"VP8L", &solid_color_image(2, 1, [1, 2, 3, 255])),
+        ]);
+        for (bytes, alpha) in [(rgba, 4), (rgb, 255)] {
+            for (layout, pixel) in [
+                (PixelLayout::Rgba8, vec![1, 2, 3, alpha]),
+                (PixelLayout::Bgra8, vec![3, 2, 1, alpha]),
+                (PixelLayout::Rgb8, vec![1, 2, 3]),
+                (PixelLayout::Bgr8, vec![3, 2, 1]),
+            ] {
+                let mut decoder = new_decoder(bytes.clone());
+                let mut buf = vec![0; 2 * layout.bytes_per_pixel()];
+                decoder.read_image_with_layout(&mut buf, layout).unwrap();
+                assert_eq!(buf, pixel.repeat(2), "{layout:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn seek_frame() {
+        let frames = [
+            (0, 0, 2, 2, 10, 0, [255, 0, 0, 255]),
+            (0, 0, 1, 1, 20, 0b01, [0, 255, 0, 255]),
+            (0, 0, 2, 1, 30, 0, [0, 0, 255, 128]),
+        ];
+        let bytes = solid_animation(2, 2, [0; 4], &frames);
+
+        let mut decoder = new_decoder(bytes);
+        let mut expected = Vec::new();
+        for _ in 0..3 {
+            let mut buf = vec![0; decoder.output_buffer_size()];
+            let duration = decoder.read_frame(&mut buf).unwrap().unwrap();
+            expected.push((buf, duration));
+        }
+
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        for index in [2, 1, 1, 0, 2] {
+            decoder.seek_frame(index).unwrap();
+            let duration = decoder.read_frame(&mut buf).unwrap().unwrap();
+            assert_eq!((&buf, duration), (&expected[index].0, expected[index].1));
+        }
+
+        assert!(matches!(
+            decoder.seek_frame(3),
+            Err(DecodingError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn reset_animation() {
+        let mut chunks = vec![vp8x(VP8X_ALPHA | VP8X_ANIMATION, 1, 1), anim([0; 4], 1)];
+        for (duration, rgba) in [(10, [255, 0, 0, 255]), (20, [0, 0, 255, 128])] {
+            let frame = solid_color_image(1, 1, rgba);
+            chunks.push(anmf(0, 0, 1, 1, duration, 0, &[chunk(b"VP8L", &frame)]));
+        }
+        let mut decoder = new_decoder(webp(&chunks));
+        let first = decoder.frames().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(first.len(), 2);
+        assert!(decoder.frames().next().is_none());
+
+        decoder.reset_animation();
+        let second = decoder.frames().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn loop_count() {
+        let frame = anmf(
+            0,
+            0,
+            1,
+            1,
+            100,
+            0,
+            &[chunk(b"VP8L", &solid_color_image(1, 1, [0; 4]))],
+        );
+        let animation = |loop_count| {
+            webp(&[
+                vp8x(VP8X_ANIMATION, 1, 1),
+                anim([0; 4], loop_count),
+                frame.clone(),
+            ])
+        };
+
+        assert_eq!(new_decoder(animation(0)).loop_count(), LoopCount::Forever);
+
+        let mut decoder = new_decoder(animation(2));
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        decoder.read_frame(&mut buf).unwrap();
+        assert_eq!(decoder.loop_count(), LoopCount::Finite(2));
+
+        let still = webp(&[chunk(b"VP8L", &solid_color_image(1, 1, [0; 4]))]);
+        assert_eq!(new_decoder(still).loop_count(), LoopCount::Finite(1));
+    }
+
+    #[test]
+    fn num_frames() {
+        let frame = (0, 0, 1, 1, 100, 0, [255, 0, 0, 255]);
+        assert_eq!(
+            new_decoder(solid_animation(1, 1, [0; 4], &[frame; 3])).num_frames(),
+            3
+        );
+
+        let vp8l = chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 4]));
+        let extended = webp(&[vp8x(VP8X_ALPHA, 1, 1), vp8l.clone()]);
+        assert_eq!(new_decoder(extended).num_frames(), 1);
+        assert_eq!(new_decoder(webp(&[vp8l])).num_frames(), 1);
+    }
+
+    #[test]
+    fn effective_is_animated() {
+        let frame = (0, 0, 1, 1, 100, 0, [255, 0, 0, 255]);
+        let decoder = new_decoder(solid_animation(1, 1, [0; 4], &[frame; 2]));
+        assert!(decoder.has_animation());
+        assert!(decoder.effective_is_animated());
+
+        let decoder = new_decoder(solid_animation(1, 1, [0; 4], &[frame]));
+        assert!(decoder.has_animation());
+        assert!(!decoder.effective_is_animated());
+
+        let vp8l = chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 4]));
+        assert!(!new_decoder(webp(&[vp8l])).effective_is_animated());
+    }
+
+    #[test]
+    fn pixel_budget() {
+        let frame = (0, 0, 2, 2, 100, 0, [255, 0, 0, 255]);
+        let bytes = solid_animation(2, 2, [0; 4], &[frame; 3]);
+
+        let mut decoder = new_decoder(bytes);
+        decoder.set_pixel_budget(10);
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        assert!(decoder.read_frame(&mut buf).unwrap().is_some());
+        assert!(decoder.read_frame(&mut buf).unwrap().is_some());
+        assert!(matches!(
+            decoder.read_frame(&mut buf),
+            Err(DecodingError::PixelBudgetExceeded(10))
+        ));
+    }
+
+    #[test]
+    fn read_frame_at() {
+        let frames: Vec<SolidFrame> = (0..5)
+            .map(|i| (i % 2, 0, 1, 1, 10 + i, 0b10, [i as u8, 0, 0, 255]))
+            .collect();
+        let bytes = solid_animation(2, 1, [0; 4], &frames);
+        let canvas_after = |index: usize| {
+            let mut decoder = new_decoder(bytes.clone());
+            let mut buf = vec![0; 8];
+            for _ in 0..=index {
+                decoder.read_frame(&mut buf).unwrap();
+            }
+            buf
+        };
+
+        for cache_size in [0, 2] {
+            let mut decoder = new_decoder(bytes.clone());
+            decoder.set_frame_cache_size(cache_size);
+            let mut buf = vec![0; 8];
+            for index in [3, 4, 3, 1, 2, 0, 4] {
+                assert_eq!(
+                    decoder.read_frame_at(index, &mut buf).unwrap(),
+                    10 + index as u32
+                );
+                assert_eq!(buf, canvas_after(index), "frame {index}");
+            }
+
+            // Playback continues after the frame that was read last.
+            assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(10));
+            assert_eq!(buf, canvas_after(0));
+            assert!(decoder.read_frame_at(5, &mut buf).is_err());
+        }
+    }
+
+    #[test]
+    fn read_frame_by_offset() {
+        let bytes = solid_animation(
+            4,
+            2,
+            [0; 4],
+            &[
+                (0, 0, 4, 2, 10, 0, [1, 1, 1, 255]),
+                (2, 0, 1, 2, 20, 0, [2, 2, 2, 128]),
+            ],
+        );
+        let mut decoder = new_decoder(bytes);
+        let offsets = decoder.frame_offsets().unwrap();
+        assert_eq!(offsets.len(), 2);
+
+        let mut buf = [0; 8];
+        assert_eq!(
+            decoder.read_frame_by_offset(offsets[1], &mut buf).unwrap(),
+            20
+        );
+        assert_eq!(buf, [2, 2, 2, 128, 2, 2, 2, 128]);
+        assert!(decoder.read_frame_by_offset(offsets[0], &mut buf).is_err());
+        assert!(decoder
+            .read_frame_by_offset(offsets[1] + 2, &mut buf)
+            .is_err());
+
+        // Playback is not affected.
+        let mut canvas = [0; 32];
+        assert_eq!(decoder.read_frame(&mut canvas).unwrap(), Some(10));
+        assert_eq!(canvas, [1, 1, 1, 255].repeat(8)[..]);
+
+        let bytes = std::fs::read("tests/images/1.webp").unwrap();
+        assert!(new_decoder(bytes).frame_offsets().unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_frame_uncomposited() {
+        let bytes = solid_animation(
+            4,
+            2,
+            [0; 4],
+            &[
+                (0, 0, 4, 2, 10, 0, [1, 1, 1, 255]),
+                (2, 0, 1, 2, 20, 0, [2, 2, 2, 128]),
+            ],
+        );
+        let mut decoder = new_decoder(bytes);
+
+        let mut buf = [0; 8];
+        assert_eq!(
+            decoder.read_frame_uncomposited(1, &mut buf).unwrap(),
+            FrameRect {
+                x: 2,
+                y: 0,
+                width: 1,
+                height: 2
+            }
+        );
+        // The frame is not blended onto the first one.
+        assert_eq!(buf, [2, 2, 2, 128, 2, 2, 2, 128]);
+        assert!(decoder.read_frame_uncomposited(0, &mut buf).is_err());
+        assert!(decoder.read_frame_uncomposited(2, &mut buf).is_err());
+
+        let mut canvas = [0; 32];
+        assert_eq!(decoder.read_frame(&mut canvas).unwrap(), Some(10));
+        assert_eq!(canvas, [1, 1, 1, 255].repeat(8)[..]);
+    }
+
+    #[test]
+    fn total_duration() {
+        let bytes = solid_animation(
+            1,
+            1,
+            [0; 4],
+            &[
+                (0, 0, 1, 1, 10, 0, [1, 1, 1, 255]),
+                (0, 0, 1, 1, 25, 0, [2, 2, 2, 255]),
+            ],
+        );
+        assert_eq!(new_decoder(bytes).total_duration().unwrap(), 35);
+
+        let frames = [(0, 0, 1, 1, 0xffffff, 0, [0; 4]); 300];
+        let bytes = solid_animation(1, 1, [0; 4], &frames);
+        assert_eq!(new_decoder(bytes).total_duration().unwrap(), u32::MAX);
+
+        let bytes = std::fs::read("tests/images/1.webp").unwrap();
+        assert_eq!(new_decoder(bytes).total_duration().unwrap(), 0);
+    }
+
+    #[test]
+    fn representative_frame() {
+        let bytes = solid_animation(
+            1,
+            1,
+            [0; 4],
+            &[
+                (0, 0, 1, 1, 10, 0, [1, 1, 1, 255]),
+                (0, 0, 1, 1, 50, 0, [2, 2, 2, 255]),
+                (0, 0, 1, 1, 20, 0, [3, 3, 3, 255]),
+                (0, 0, 1, 1, 50, 0, [4, 4, 4, 255]),
+            ],
+        );
+        let mut decoder = new_decoder(bytes);
+        let mut buf = [0; 4];
+        decoder.read_frame(&mut buf).unwrap();
+        decoder.read_frame(&mut buf).unwrap();
+        decoder.read_frame(&mut buf).unwrap();
+
+        assert_eq!(decoder.read_representative_frame(&mut buf).unwrap(), 1);
+        assert_eq!(buf, [2, 2, 2, 255]);
+        assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(20));
+        assert_eq!(buf, [3, 3, 3, 255]);
+    }
+
+    #[test]
+    fn max_scan_chunks() {
+        // VP8X, ANIM and three ANMF chunks.
+        let bytes = solid_animation(1, 1, [0; 4], &[(0, 0, 1, 1, 10, 0, [1, 2, 3, 4]); 3]);
+        assert!(WebPDecoder::new_with_max_scan_chunks(Cursor::new(bytes.clone()), 5).is_ok());
+        assert!(matches!(
+            WebPDecoder::new_with_max_scan_chunks(Cursor::new(bytes), 4),
+            Err(DecodingError::TooManyChunks(4))
+        ));
+    }
+
+    #[test]
+    fn dirty_rect() {
+        let bytes = solid_animation(
+            4,
+            4,
+            [0; 4],
+            &[
+                (0, 0, 4, 4, 10, 0b10, [1, 1, 1, 255]),
+                (2, 0, 2, 2, 10, 0b11, [2, 2, 2, 255]),
+                (2, 2, 2, 2, 10, 0b10, [3, 3, 3, 255]),
+            ],
+        );
+        let mut decoder = new_decoder(bytes);
+        let mut buf = vec![0; 4 * 4 * 4];
+
+        let full = FrameRect {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 4,
+        };
+        let second = FrameRect {
+            x: 2,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        assert_eq!(
+            decoder.read_frame_dirty(&mut buf).unwrap(),
+            Some((10, full))
+        );
+        assert_eq!(
+            decoder.read_frame_dirty(&mut buf).unwrap(),
+            Some((10, second))
+        );
+        // The second frame is disposed, so its area changes along with that of the third frame.
+        let disposed_and_third = FrameRect {
+            x: 2,
+            y: 0,
+            width: 2,
+            height: 4,
+        };
+        assert_eq!(
+            decoder.read_frame_dirty(&mut buf).unwrap(),
+            Some((10, disposed_and_third))
+        );
+    }
+
+    #[test]
+    fn image_storage() {
+        let bytes = std::fs::read("tests/images/1_webp_a.webp").unwrap();
+        assert_eq!(new_decoder(bytes).image_storage(), ImageStorage::TopLevel);
+
+        let bytes = solid_animation(1, 1, [0; 4], &[(0, 0, 1, 1, 10, 0, [0; 4])]);
+        assert_eq!(
+            new_decoder(bytes).image_storage(),
+            ImageStorage::InAnimationFrame
+        );
+    }
+
+    #[test]
+    fn capabilities() {
+        let capabilities = super::capabilities();
+        assert!(capabilities.vp8_keyframes && capabilities.vp8l && capabilities.animation);
+        assert!(!capabilities.vp8_interframes);
+        assert_eq!(capabilities.color_management, cfg!(feature = "color"));
+    }
+
+    #[test]
+    fn quick_classify() {
+        for (file, class) in [
+            ("1", WebPClass::Lossy),
+            ("1_webp_ll", WebPClass::Lossless),
+            ("1_webp_a", WebPClass::Extended),
+        ] {
+            let bytes = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+            assert_eq!(super::quick_classify(&bytes[..16]), Some(class));
+        }
+
+        let bytes = std::fs::read("tests/images/1.webp").unwrap();
+        assert_eq!(super::quick_classify(&bytes[..15]), None);
+        assert_eq!(super::quick_classify(b"RIFF\0\0\0\0WEBPICCP"), None);
+        assert_eq!(super::quick_classify(b"RIFF\0\0\0\0WAVEfmt "), None);
+    }
+
+    #[test]
+    fn blend_space() {
+        // Half transparent white blended over opaque black.
+        let bytes = solid_animation(
+            1,
+            1,
+            [0; 4],
+            &[
+                (0, 0, 1, 1, 10, 0, [0, 0, 0, 255]),
+                (0, 0, 1, 1, 10, 0, [255, 255, 255, 128]),
+            ],
+        );
+
+        for (blend_space, expected) in [(BlendSpace::Srgb, 128), (BlendSpace::Linear, 188)] {
+            let mut decoder = new_decoder(bytes.clone());
+            decoder.set_blend_space(blend_space);
+
+            let mut buf = [0; 4];
+            decoder.read_frame(&mut buf).unwrap();
+            assert_eq!(buf, [0, 0, 0, 255]);
+            decoder.read_frame(&mut buf).unwrap();
+            assert_eq!(buf, [expected, expected, expected, 255], "{blend_space:?}");
+        }
+    }
+
+    #[test]
+    fn decode_raw() {
+        let bytes = std::fs::read("tests/images/1.webp").unwrap();
+        let mut decoder = new_decoder(bytes.clone());
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        decoder.read_image(&mut buf).unwrap();
+
+        let image = new_decoder(bytes).decode_raw().unwrap();
+        assert_eq!((image.width, image.height), (550, 368));
+        assert_eq!(image.format, PixelFormat::Rgb8);
+        assert_eq!(image.data, buf);
+        assert_eq!(&image.as_rgb()[..], &buf[..]);
+
+        let rgba = image.as_rgba();
+        assert_eq!(rgba.len(), 550 * 368 * 4);
+        assert_eq!(&rgba[..4], &[buf[0], buf[1], buf[2], 255]);
+
+        let bytes = webp(&[chunk(b"VP8L", &solid_color_image(2, 1, [1, 2, 3, 4]))]);
+        let image = new_decoder(bytes).decode_raw().unwrap();
+        assert_eq!(image.format, PixelFormat::Rgba8);
+        assert_eq!(&image.as_rgba()[..], &[1, 2, 3, 4, 1, 2, 3, 4]);
+        assert_eq!(&image.as_rgb()[..], &[1, 2, 3, 1, 2, 3]);
+    }
+
+    #[cfg(feature = "color")]
+    #[test]
+    fn read_image_srgb() {
+        let bytes = std::fs::read("tests/images/1_webp_a.webp").unwrap();
+        let mut decoder = new_decoder(bytes.clone());
+        let mut expected = vec![0; decoder.output_buffer_size()];
+        decoder.read_image(&mut expected).unwrap();
+
+        let mut buf = vec![0; expected.len()];
+        new_decoder(bytes).read_image_srgb(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+
+        let icc_flag = 0b00100000;
+        let bytes = webp(&[
+            vp8x(icc_flag, 1, 1),
+            chunk(b"ICCP", b"not an icc profile"),
+            chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 4])),
+        ]);
+        let mut buf = [0; 3];
+        new_decoder(bytes.clone())
+            .read_image_srgb(&mut buf)
+            .unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+
+        let mut decoder = new_decoder(bytes);
+        decoder.set_require_known_colorspace(true);
+        assert!(matches!(
+            decoder.read_image_srgb(&mut buf),
+            Err(DecodingError::IccProfileInvalid)
+        ));
+    }
+
+    #[test]
+    fn alpha_is_binary() {
+        for (alpha, expected) in [(0, true), (255, true), (128, false)] {
+            let bytes = webp(&[chunk(b"VP8L", &solid_color_image(2, 2, [1, 2, 3, alpha]))]);
+            assert_eq!(new_decoder(bytes).alpha_is_binary().unwrap(), expected);
+        }
+
+        let bytes = std::fs::read("tests/images/1.webp").unwrap();
+        assert!(!new_decoder(bytes).alpha_is_binary().unwrap());
+    }
+
+    #[test]
+    fn read_distinct_frames() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let bytes = solid_animation(
+            2,
+            2,
+            [0; 4],
+            &[
+                (0, 0, 2, 2, 10, 0, red),
+                (0, 0, 2, 2, 20, 0, red),
+                (0, 0, 2, 2, 40, 0, blue),
+                (0, 0, 2, 2, 80, 0, red),
+            ],
+        );
+
+        let frames = new_decoder(bytes).read_distinct_frames().unwrap();
+        let durations: Vec<_> = frames.iter().map(|(duration, _)| *duration).collect();
+        assert_eq!(durations, [30, 40, 80]);
+        assert_eq!(frames[0].1, red.repeat(4));
+        assert_eq!(frames[1].1, blue.repeat(4));
+        assert_eq!(frames[2].1, red.repeat(4));
+    }
+
+    #[test]
+    fn missing_chunks() {
+        let image = chunk(b"VP8L", &solid_color_image(1, 1, [0; 4]));
+        let icc_flag = 0b00100000;
+        for (chunks, fourcc) in [
+            (vec![vp8x(icc_flag, 1, 1), image.clone()], *b"ICCP"),
+            (vec![vp8x(0, 1, 1)], *b"VP8 "),
+            (vec![vp8x(VP8X_ANIMATION, 1, 1), anim([0; 4], 0)], *b"ANMF"),
+        ] {
+            match WebPDecoder::new(Cursor::new(webp(&chunks))) {
+                Err(DecodingError::ChunkMissing(missing)) => assert_eq!(missing, fourcc),
+                Err(e) => panic!("unexpected error {e:?}"),
+                Ok(_) => panic!("missing {fourcc:?} not detected"),
+            }
+        }
+    }
+
+    #[test]
+    fn lenient() {
+        let image = chunk(b"VP8L", &solid_color_image(1, 1, [1, 2, 3, 255]));
+        let icc_flag = 0b00100000;
+        let bytes = webp(&[vp8x(icc_flag | VP8X_EXIF, 1, 1), image.clone()]);
+        assert!(WebPDecoder::new(Cursor::new(bytes.clone())).is_err());
+        let mut decoder = WebPDecoder::new_lenient(Cursor::new(bytes)).unwrap();
+        assert_eq!(decoder.icc_profile().unwrap(), None);
+        assert_eq!(decoder.exif_metadata().unwrap(), None);
+
+        let bytes = webp(&[vp8x(VP8X_ANIMATION, 1, 1), anim([0; 4], 0), image.clone()]);
+        let mut decoder = WebPDecoder::new_lenient(Cursor::new(bytes)).unwrap();
+        assert!(!decoder.has_animation());
+        let mut buf = [0; 3];
+        decoder.read_image(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+
+        let bytes = webp(&[
+            vp8x(VP8X_ANIMATION | VP8X_ALPHA, 1, 1),
+            anmf(0, 0, 1, 1, 10, 0, &[image]),
+        ]);
+        let mut decoder = WebPDecoder::new_lenient(Cursor::new(bytes)).unwrap();
+        assert_eq!(decoder.loop_count(), LoopCount::Forever);
+        let mut buf = [0; 4];
+        assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(10));
+        assert_eq!(buf, [1, 2, 3, 255]);
+
+        let (vp8, width, height) = lossy_chunk();
+        let bytes = webp(&[vp8x(VP8X_ALPHA, width, height), vp8]);
+        let decoder = WebPDecoder::new_lenient(Cursor::new(bytes)).unwrap();
+        assert!(!decoder.has_alpha());
+    }
+
+    #[test]
+    fn read_frames_at_fps() {
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let bytes = solid_animation(
+            1,
+            1,
+            [0; 4],
+            &[
+                (0, 0, 1, 1, 100, 0, colors[0]),
+                (0, 0, 1, 1, 50, 0, colors[1]),
+                (0, 0, 1, 1, 250, 0, colors[2]),
+            ],
+        );
+
+        let mut decoder = new_decoder(bytes);
+        let frames: Vec<_> = decoder
+            .read_frames_at_fps(10)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(frames, [colors[0], colors[1], colors[2], colors[2]]);
+
+        assert!(decoder.read_frames_at_fps(0).is_err());
+
+        let bytes = solid_animation(
+            1,
+            1,
+            [0; 4],
+            &[(0, 0, 1, 1, 0, 0, colors[0]), (0, 0, 1, 1, 0, 0, colors[1])],
+        );
+        let mut decoder = new_decoder(bytes);
+        let frames: Vec<_> = decoder
+            .read_frames_at_fps(10)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(frames, [colors[1]]);
+    }
+
+    #[test]
+    fn zero_canvas_size() {
+        for offset in [14, 16] {
+            let (mut vp8, _, _) = lossy_chunk();
+            vp8[offset] = 0;
+            vp8[offset + 1] &= 0xC0;
+            assert!(matches!(
+                WebPDecoder::new(Cursor::new(webp(&[vp8]))),
+                Err(DecodingError::InfoBitsInvalid {
+                    name: "canvas_size",
+                    value: 0
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn luma_histogram() {
+        let bytes = std::fs::read("tests/images/1.webp").unwrap();
+        let histogram = new_decoder(bytes).luma_histogram().unwrap();
+        assert_eq!(histogram.iter().sum::<u32>(), 550 * 368);
+
+        for (rgb, luma) in [([0, 0, 0], 16), ([255, 255, 255], 235)] {
+            let color = [rgb[0], rgb[1], rgb[2], 255];
+            let bytes = webp(&[chunk(b"VP8L", &solid_color_image(3, 2, color))]);
+            let histogram = new_decoder(bytes).luma_histogram().unwrap();
+            assert_eq!(histogram[luma], 6);
+            assert_eq!(histogram.iter().sum::<u32>(), 6);
+        }
+
+        // A lossy first frame that does not cover the canvas is composited like playback does.
+        let (vp8, width, height) = lossy_chunk();
+        let bytes = webp(&[
+            vp8x(VP8X_ANIMATION, width + 2, height),
+            anim([0, 0, 0, 255], 0),
+            anmf(2, 0, width, height, 100, 0, &[vp8]),
+        ]);
+        let mut decoder = new_decoder(bytes);
+        let histogram = decoder.luma_histogram().unwrap();
+        let mut buf = vec![0; decoder.output_buffer_size()];
+        decoder.read_frame(&mut buf).unwrap();
+        let mut expected = [0u32; 256];
+        for pixel in buf.chunks_exact(decoder.output_format().bytes_per_pixel()) {
+            expected[usize::from(rgb_to_luma(pixel))] += 1;
+        }
+        assert_eq!(histogram, expected);
+        assert_eq!(histogram.iter().sum::<u32>(), (width + 2) * height);
+        assert!(histogram[16] >= 2 * height);
+    }
+
+    #[test]
+    fn pixels_equal() {
+        let opaque = webp(&[chunk(b"VP8L", &solid_color_image(2, 2, [1, 2, 3, 255]))]);
+        let translucent = webp(&[chunk(b"VP8L", &solid_color_image(2, 2, [1, 2, 3, 4]))]);
+        let wide = webp(&[chunk(b"VP8L", &solid_color_image(4, 1, [1, 2, 3, 255]))]);
+        let animation = |color| solid_animation(2, 2, [0; 4], &[(0, 0, 2, 2, 10, 0, color)]);
+
+        let equal = |a: &[u8], b: &[u8]| {
+            new_decoder(a.to_vec())
+                .pixels_equal(&mut new_decoder(b.to_vec()))
+                .unwrap()
+        };
+        assert!(equal(&opaque, &opaque));
+        assert!(!equal(&opaque, &translucent));
+        assert!(!equal(&opaque, &wide));
+        assert!(!equal(&opaque, &animation([1, 2, 3, 255])));
+        assert!(equal(&animation([1, 2, 3, 4]), &animation([1, 2, 3, 4])));
+        assert!(!equal(&animation([1, 2, 3, 4]), &animation([1, 2, 3, 5])));
+
+        let lossy = std::fs::read("tests/images/1.webp").unwrap();
+        assert!(equal(&lossy, &lossy));
+    }
+
+    #[test]
+    fn huffman_strategy() {
+        for file in ["1_webp_ll", "2_webp_ll", "3_webp_a"] {
+            let bytes = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+            let expected = new_decoder(bytes.clone()).decode_raw().unwrap();
+
+            let mut decoder = new_decoder(bytes);
+            decoder.set_huffman_strategy(HuffmanStrategy::FastTable);
+            assert_eq!(decoder.decode_raw().unwrap(), expected, "{file}");
+        }
+    }
+
+    #[test]
+    fn read_image_borrowed() {
+        let bytes = std::fs::read("tests/images/2_webp_ll.webp").unwrap();
+        let expected = new_decoder(bytes.clone()).decode_raw().unwrap();
+
+        let mut decoder = new_decoder(bytes);
+        for _ in 0..2 {
+            let view = decoder.read_image_borrowed().unwrap();
+            assert_eq!((view.width, view.height), (expected.width, expected.height));
+            assert_eq!(view.format, expected.format);
+            assert_eq!(view.data, &expected.data[..]);
+        }
+    }
+
+    #[test]
+    fn alpha_frames_with_and_without_padding() {
+        let (vp8, width, height) = lossy_chunk();
+        let mut alpha = vec![0];
+        alpha.resize(1 + (width * height) as usize, 128);
+        assert_eq!(alpha.len() % 2, 1);
+
+        let rgb = new_decoder(std::fs::read("tests/images/1.webp").unwrap())
+            .decode_raw()
+            .unwrap();
+        let mut expected = rgb.as_rgba().into_owned();
+        for pixel in expected.chunks_exact_mut(4) {
+            pixel[3] = 128;
+        }
+        let second = [1, 2, 3, 255];
+
+        for padded in [true, false] {
+            let mut alph = chunk(b"ALPH", &alpha);
+            if !padded {
+                alph.pop();
+            }
+            let bytes = webp(&[
+                vp8x(VP8X_ALPHA | VP8X_ANIMATION, width, height),
+                anim([0; 4], 0),
+                anmf(0, 0, width, height, 10, 0b10, &[alph, vp8.clone()]),
+                anmf(
+                    0,
+                    0,
+                    1,
+                    1,
+                    20,
+                    0b10,
+                    &[chunk(b"VP8L", &solid_color_image(1, 1, second))],
+                ),
+            ]);
+
+            let mut decoder = new_decoder(bytes);
+            let mut buf = vec![0; decoder.output_buffer_size()];
+            assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(10));
+            assert!(buf == expected, "padded: {padded}");
+            assert_eq!(decoder.read_frame(&mut buf).unwrap(), Some(20));
+            assert_eq!(buf[..4], second);
+        }
+    }
+
+    #[test]
+    fn first_frame_is_full_keyframe() {
+        let color = [1, 2, 3, 4];
+        for (frame, expected) in [
+            ((0, 0, 4, 4, 10, 0b10, color), true),
+            ((0, 0, 4, 4, 10, 0, color), false),
+            ((2, 0, 2, 4, 10, 0b10, color), false),
+        ] {
+            let bytes = solid_animation(4, 4, [0; 4], &[frame]);
+            assert_eq!(
+                new_decoder(bytes).first_frame_is_full_keyframe().unwrap(),
+                expected,
+                "{frame:?}"
+            );
+        }
+
+        // Blending an opaque lossy frame still replaces the canvas.
+        let (vp8, width, height) = lossy_chunk();
+        let bytes = webp(&[
+            vp8x(VP8X_ANIMATION, width, height),
+            anim([0; 4], 0),
+            anmf(0, 0, width, height, 10, 0, &[vp8]),
+        ]);
+        assert!(new_decoder(bytes).first_frame_is_full_keyframe().unwrap());
+
+        let bytes = std::fs::read("tests/images/1.webp").unwrap();
+        assert!(new_decoder(bytes).first_frame_is_full_keyframe().unwrap());
+    }
+
+    #[test]
+    fn dispose_to_background() {
+        let blue = [0, 0, 255, 255];
+        let bytes = solid_animation(
+            4,
+            1,
+            [0; 4],
+            &[
+                (0, 0, 4, 1, 10, 0b10, blue),
+                (0, 0, 2, 1, 10, 0b11, [255, 0, 0, 255]),
+                (0, 0, 4, 1, 10, 0, [255, 255, 255, 128]),
+            ],
+        );
+        let mut decoder = new_decoder(bytes);
+        let mut buf = vec![0; 4 * 4];
+        decoder.read_frame(&mut buf).unwrap();
+        decoder.read_frame(&mut buf).unwrap();
+        decoder.read_frame(&mut buf).unwrap();
+
+        // Only the red frame's area was reset to the transparent background before blending, the
+        // rest of the canvas still holds the first frame.
+        assert_eq!(buf[..8], [255, 255, 255, 128, 255, 255, 255, 128]);
+        assert_eq!(buf[8..], [128, 128, 255, 255, 128, 128, 255, 255]);
+    }
+
+    #[test]
+    fn semi_transparent_background() {
+        let background = [255, 0, 0, 128];
+        let blue = [0, 0, 255, 128];
+        // Blue blended over the background, with the alpha values combined like
+        // `128 + 128 * (1 - 128 / 255)`.
+        let blended = [84, 0, 170, 191];
+        let bytes = solid_animation(
+            3,
+            1,
+            [0, 0, 255, 128],
+            &[(0, 0, 1, 1, 10, 0, blue), (2, 0, 1, 1, 10, 0, blue)],
+        );
+        let mut decoder = new_decoder(bytes);
+        let mut buf = [0; 12];
+        for _ in 0..2 {
+            // Each loop starts from a canvas filled with the background color.
+            decoder.read_frame(&mut buf).unwrap();
+            assert_eq!(buf, [blended, background, background].concat()[..]);
+            decoder.read_frame(&mut buf).unwrap();
+            assert_eq!(buf, [blended, background, blended].concat()[..]);
+        }
+
+        let bytes = solid_animation(2, 1, [0, 0, 255, 128], &[(0, 0, 1, 1, 10, 0, blue)]);
+        let mut decoder = new_decoder(bytes);
+        let mut buf = [0; 8];
+        decoder.read_frame(&mut buf).unwrap();
+        assert_eq!(buf, [blended, background].concat()[..]);
+    }
 
     #[test]
     fn add_with_overflow_size() {
@@ -0,0 +1,368 @@
+//! Entropy decoding backends
+//!
+//! The VP8 and VP8L decoders read their bitstreams through the [`BoolDecoder`] and [`BitRead`]
+//! traits instead of concrete types. The backend that is actually used is picked at compile
+//! time through the [`Vp8BoolReader`] and [`LosslessBitReader`] aliases:
+//!
+//! * By default, the scalar readers in `vp8` and `lossless` are used. They consume the input
+//!   one byte (VP8) or one bit (VP8L) at a time.
+//! * With the `buffered-entropy` feature, the readers in this module are used instead. They
+//!   load up to 7 bytes of input at once into a 64-bit window, which cuts down on the number
+//!   of bounds checks in the hot path.
+//!
+//! Both backends decode exactly the same values from the same input, including when reading
+//! past the end of the data.
+
+use std::convert::TryFrom;
+
+use crate::decoder::DecodingError;
+
+/// The boolean entropy decoder used for VP8 data, as described in RFC-6386 section 7.
+#[cfg(not(feature = "buffered-entropy"))]
+pub(crate) type Vp8BoolReader = crate::vp8::BoolReader;
+/// The boolean entropy decoder used for VP8 data, as described in RFC-6386 section 7.
+#[cfg(feature = "buffered-entropy")]
+pub(crate) type Vp8BoolReader = BufferedBoolReader;
+
+/// The LSB-first bit reader used for VP8L data.
+#[cfg(not(feature = "buffered-entropy"))]
+pub(crate) type LosslessBitReader = crate::lossless::BitReader;
+/// The LSB-first bit reader used for VP8L data.
+#[cfg(feature = "buffered-entropy")]
+pub(crate) type LosslessBitReader = BufferedBitReader;
+
+/// A boolean entropy decoder for VP8 partitions
+pub(crate) trait BoolDecoder {
+    /// Starts decoding `buf` from the beginning
+    fn init(&mut self, buf: Vec<u8>) -> Result<(), DecodingError>;
+
+    /// Reads a single bool that is `false` with the given probability (out of 256)
+    ///
+    /// Reading past the end of the data behaves as if the data was padded with zeros.
+    fn read_bool(&mut self, probability: u8) -> bool;
+
+    /// Returns how many zero bits past the end of the data have entered the 8-bit decoding
+    /// window so far. This is zero as long as the data was long enough.
+    fn overread_bits(&self) -> usize;
+
+    /// Reads an `n` bit unsigned literal, most significant bit first
+    fn read_literal(&mut self, n: u8) -> u8 {
+        let mut v = 0u8;
+        let mut n = n;
+
+        while n != 0 {
+            v = (v << 1) + self.read_bool(128u8) as u8;
+            n -= 1;
+        }
+
+        v
+    }
+
+    /// Reads an `n` bit magnitude followed by a sign bit
+    fn read_magnitude_and_sign(&mut self, n: u8) -> i32 {
+        let magnitude = self.read_literal(n);
+        let sign = self.read_literal(1);
+
+        if sign == 1 {
+            -i32::from(magnitude)
+        } else {
+            i32::from(magnitude)
+        }
+    }
+
+    /// Reads a value using a token tree and its probabilities
+    fn read_with_tree(&mut self, tree: &[i8], probs: &[u8], start: isize) -> i8 {
+        let mut index = start;
+
+        loop {
+            let a = self.read_bool(probs[index as usize >> 1]);
+            let b = index + a as isize;
+            index = tree[b as usize] as isize;
+
+            if index <= 0 {
+                break;
+            }
+        }
+
+        -index as i8
+    }
+
+    /// Reads a single bit with even probability
+    fn read_flag(&mut self) -> bool {
+        0 != self.read_literal(1)
+    }
+}
+
+/// An LSB-first bit reader for VP8L data
+pub(crate) trait BitRead {
+    /// Starts reading `buf` from the beginning
+    fn init(&mut self, buf: Vec<u8>);
+
+    /// Reads `num` bits (at most 32), the first bit read being the least significant one
+    ///
+    /// Returns an error if fewer than `num` bits are left.
+    fn read_u32(&mut self, num: u8) -> Result<u32, DecodingError>;
+
+    /// Returns the next `num` bits (at most 32) without consuming them
+    ///
+    /// Bits past the end of the data read as zero.
+    fn peek_u32(&mut self, num: u8) -> u32;
+
+    /// Skips over `num` bits (at most 32)
+    ///
+    /// Returns an error if fewer than `num` bits are left.
+    fn consume(&mut self, num: u8) -> Result<(), DecodingError> {
+        self.read_u32(num).map(|_| ())
+    }
+
+    /// Reads `num` bits into any integer type that can hold them
+    fn read_bits<T: TryFrom<u32>>(&mut self, num: u8) -> Result<T, DecodingError> {
+        T::try_from(self.read_u32(num)?).map_err(|_| DecodingError::BitStreamError)
+    }
+}
+
+/// A [`BoolDecoder`] that decodes from a 64-bit window of the input
+///
+/// This follows the approach of libwebp's `VP8BitReader`: `value` holds the not yet consumed
+/// bits of the input, and `bits` is the position of the 8-bit decoding window within it. Once
+/// the window runs out, up to 7 new bytes are shifted in at once.
+#[cfg(any(test, feature = "buffered-entropy"))]
+pub(crate) struct BufferedBoolReader {
+    buf: Vec<u8>,
+    index: usize,
+    /// The number of zero bytes loaded after reaching the end of `buf`
+    padding_bytes: usize,
+
+    value: u64,
+    /// The current range minus one
+    range: u32,
+    bits: i32,
+}
+
+#[cfg(any(test, feature = "buffered-entropy"))]
+impl BufferedBoolReader {
+    pub(crate) fn new() -> BufferedBoolReader {
+        BufferedBoolReader {
+            buf: Vec::new(),
+            index: 0,
+            padding_bytes: 0,
+            value: 0,
+            range: 255 - 1,
+            bits: -8,
+        }
+    }
+
+    fn load_new_bytes(&mut self) {
+        if let Some(bytes) = self.buf.get(self.index..self.index + 7) {
+            let mut be = [0u8; 8];
+            be[1..].copy_from_slice(bytes);
+            self.value = (self.value << 56) | u64::from_be_bytes(be);
+            self.bits += 56;
+            self.index += 7;
+        } else {
+            // Past the end of the data, zeros are shifted in, just like the scalar reader does.
+            let byte = match self.buf.get(self.index) {
+                Some(&byte) => {
+                    self.index += 1;
+                    byte
+                }
+                None => {
+                    self.padding_bytes += 1;
+                    0
+                }
+            };
+            self.value = (self.value << 8) | u64::from(byte);
+            self.bits += 8;
+        }
+    }
+}
+
+#[cfg(any(test, feature = "buffered-entropy"))]
+impl BoolDecoder for BufferedBoolReader {
+    fn init(&mut self, buf: Vec<u8>) -> Result<(), DecodingError> {
+        if buf.len() < 2 {
+            return Err(DecodingError::NotEnoughInitData);
+        }
+
+        self.buf = buf;
+        self.index = 0;
+        self.padding_bytes = 0;
+        self.value = 0;
+        self.range = 255 - 1;
+        self.bits = -8;
+        self.load_new_bytes();
+
+        Ok(())
+    }
+
+    fn read_bool(&mut self, probability: u8) -> bool {
+        if self.bits < 0 {
+            self.load_new_bytes();
+        }
+
+        let pos = self.bits as u32;
+        let split = (self.range * u32::from(probability)) >> 8;
+        let value = (self.value >> pos) as u32;
+
+        let (retval, mut range) = if value > split {
+            self.value -= u64::from(split + 1) << pos;
+            (true, self.range - split)
+        } else {
+            (false, split + 1)
+        };
+
+        // Normalize the range back into [128, 255].
+        let shift = 7 ^ (31 - range.leading_zeros());
+        range <<= shift;
+        self.bits -= shift as i32;
+        self.range = range - 1;
+
+        retval
+    }
+
+    fn overread_bits(&self) -> usize {
+        // Every normalization shift moves the window by one bit. The window starts at bit
+        // `8 * loaded - 8 - bits` of the data.
+        let loaded = self.index + self.padding_bytes;
+        let position = (8 * loaded as i64 - 8 - i64::from(self.bits)) as usize;
+        (position + 8).saturating_sub(8 * self.buf.len())
+    }
+}
+
+/// A [`BitRead`] implementation that refills a 64-bit window several bytes at a time
+#[cfg(any(test, feature = "buffered-entropy"))]
+#[derive(Debug, Clone)]
+pub(crate) struct BufferedBitReader {
+    buf: Vec<u8>,
+    index: usize,
+
+    value: u64,
+    nbits: u8,
+}
+
+#[cfg(any(test, feature = "buffered-entropy"))]
+impl BufferedBitReader {
+    pub(crate) fn new() -> BufferedBitReader {
+        BufferedBitReader {
+            buf: Vec::new(),
+            index: 0,
+            value: 0,
+            nbits: 0,
+        }
+    }
+
+    fn refill(&mut self) {
+        while self.nbits <= 56 && self.index < self.buf.len() {
+            self.value |= u64::from(self.buf[self.index]) << self.nbits;
+            self.nbits += 8;
+            self.index += 1;
+        }
+    }
+}
+
+#[cfg(any(test, feature = "buffered-entropy"))]
+impl BitRead for BufferedBitReader {
+    fn init(&mut self, buf: Vec<u8>) {
+        self.buf = buf;
+        self.index = 0;
+        self.value = 0;
+        self.nbits = 0;
+    }
+
+    fn peek_u32(&mut self, num: u8) -> u32 {
+        debug_assert!(num <= 32);
+
+        if num > self.nbits {
+            self.refill();
+        }
+        (self.value & ((1u64 << num) - 1)) as u32
+    }
+
+    fn read_u32(&mut self, num: u8) -> Result<u32, DecodingError> {
+        debug_assert!(num <= 32);
+
+        if num > self.nbits {
+            self.refill();
+            if num > self.nbits {
+                return Err(DecodingError::BitStreamError);
+            }
+        }
+
+        let value = self.value & ((1u64 << num) - 1);
+        self.value >>= num;
+        self.nbits -= num;
+
+        Ok(value as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lossless::BitReader;
+    use crate::vp8::BoolReader;
+
+    /// Deterministic pseudo-random bytes, so that both backends see non-trivial input.
+    fn noise(len: usize, mut seed: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bool_readers_agree() {
+        for len in [2, 3, 8, 9, 100] {
+            let data = noise(len, 0x1234_5678 + len as u32);
+
+            let mut scalar = BoolReader::new();
+            let mut buffered = BufferedBoolReader::new();
+            scalar.init(data.clone()).unwrap();
+            buffered.init(data).unwrap();
+
+            // Read well past the end of the data to check the zero padding behaviour as well.
+            for i in 0..len * 16 + 64 {
+                let probability = (i * 37 % 255) as u8 + 1;
+                assert_eq!(
+                    scalar.read_bool(probability),
+                    buffered.read_bool(probability),
+                    "bool {i} of {len} bytes"
+                );
+                assert_eq!(scalar.overread_bits(), buffered.overread_bits());
+            }
+            assert!(scalar.overread_bits() > 0);
+        }
+    }
+
+    #[test]
+    fn bool_readers_need_two_bytes() {
+        assert!(BoolReader::new().init(vec![0]).is_err());
+        assert!(BufferedBoolReader::new().init(vec![0]).is_err());
+    }
+
+    #[test]
+    fn bit_readers_agree() {
+        let data = noise(97, 0xdead_beef);
+
+        let mut scalar = BitReader::new();
+        let mut buffered = BufferedBitReader::new();
+        scalar.init(data.clone());
+        buffered.init(data);
+
+        let mut num = 0;
+        loop {
+            num = (num + 7) % 33;
+            assert_eq!(scalar.peek_u32(num), buffered.peek_u32(num));
+            let (a, b) = (scalar.read_u32(num), buffered.read_u32(num));
+            assert_eq!(a.is_ok(), b.is_ok());
+            match (a, b) {
+                (Ok(a), Ok(b)) => assert_eq!(a, b),
+                _ => break,
+            }
+        }
+    }
+}
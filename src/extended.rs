@@ -1,5 +1,5 @@
 use super::lossless::LosslessDecoder;
-use crate::decoder::DecodingError;
+use crate::decoder::{AlphaEncodingInfo, BlendSpace, DecodingError, FrameRect, HuffmanStrategy};
 use byteorder::ReadBytesExt;
 use std::convert::TryInto;
 use std::io::{self, Read};
@@ -16,19 +16,20 @@ pub(crate) struct WebPExtendedInfo {
     pub(crate) xmp_metadata: bool,
     pub(crate) animation: bool,
 
+    /// The background color of the canvas in RGBA order
     pub(crate) background_color: [u8; 4],
 }
 
 /// Composites a frame onto a canvas.
 ///
-/// Starts by filling the canvas with the background color, if provided. Then copies or blends the
-/// frame onto the canvas.
+/// Starts by filling the given rectangle of the canvas with the background color, if provided. Then
+/// copies or blends the frame onto the canvas.
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn composite_frame(
     canvas: &mut [u8],
     canvas_width: u32,
     canvas_height: u32,
-    clear_color: Option<[u8; 4]>,
+    clear: Option<(FrameRect, [u8; 4])>,
     frame: &[u8],
     frame_offset_x: u32,
     frame_offset_y: u32,
@@ -36,6 +37,7 @@ pub(crate) fn composite_frame(
     frame_height: u32,
     frame_has_alpha: bool,
     frame_use_alpha_blending: bool,
+    blend_space: BlendSpace,
 ) {
     if frame_offset_x == 0
         && frame_offset_y == 0
@@ -54,15 +56,12 @@ pub(crate) fn composite_frame(
         return;
     }
 
-    if let Some(clear_color) = clear_color {
-        if frame_has_alpha {
-            for pixel in canvas.chunks_exact_mut(4) {
+    if let Some((rect, clear_color)) = clear {
+        for y in rect.y..rect.y + rect.height {
+            let start = (y * canvas_width + rect.x) as usize * 4;
+            for pixel in canvas[start..][..rect.width as usize * 4].chunks_exact_mut(4) {
                 pixel.copy_from_slice(&clear_color);
             }
-        } else {
-            for pixel in canvas.chunks_exact_mut(3) {
-                pixel.copy_from_slice(&clear_color[..3]);
-            }
         }
     }
 
@@ -80,22 +79,29 @@ pub(crate) fn composite_frame(
                 let input = &frame[frame_index..][..4];
                 let output = &mut canvas[canvas_index..][..4];
 
-                let blended =
-                    do_alpha_blending(input.try_into().unwrap(), output.try_into().unwrap());
+                let (input, canvas) = (input.try_into().unwrap(), output.try_into().unwrap());
+                let blended = match blend_space {
+                    BlendSpace::Srgb => do_alpha_blending(input, canvas),
+                    BlendSpace::Linear => do_linear_alpha_blending(input, canvas),
+                };
                 output.copy_from_slice(&blended);
             }
         }
     } else if frame_has_alpha {
         for y in 0..height {
-            let frame_index = (y * frame_width as usize) * 3;
-            let canvas_index = (y + frame_offset_y as usize) * canvas_width as usize * 4;
+            let frame_index = (y * frame_width as usize) * 4;
+            let canvas_index = ((y + frame_offset_y as usize) * canvas_width as usize
+                + frame_offset_x as usize)
+                * 4;
 
-            canvas[canvas_index..][..width * 4].copy_from_slice(&frame[frame_index..][..width * 3]);
+            canvas[canvas_index..][..width * 4].copy_from_slice(&frame[frame_index..][..width * 4]);
         }
     } else {
         for y in 0..height {
             let index = (y * frame_width as usize) * 3;
-            let canvas_index = (y + frame_offset_y as usize) * canvas_width as usize * 4;
+            let canvas_index = ((y + frame_offset_y as usize) * canvas_width as usize
+                + frame_offset_x as usize)
+                * 4;
 
             let input = &frame[index..][..width * 3];
             let output = &mut canvas[canvas_index..][..width * 4];
@@ -136,12 +142,74 @@ fn do_alpha_blending(buffer: [u8; 4], canvas: [u8; 4]) -> [u8; 4] {
     [blend_rgb[0], blend_rgb[1], blend_rgb[2], blend_alpha]
 }
 
+fn srgb_to_linear(value: u8) -> f64 {
+    let value = f64::from(value) / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u8 {
+    let value = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Blends like [`do_alpha_blending`], but on premultiplied linear light values instead of the
+/// stored sRGB values. The resulting alpha is the same in both cases.
+fn do_linear_alpha_blending(buffer: [u8; 4], canvas: [u8; 4]) -> [u8; 4] {
+    let canvas_alpha = f64::from(canvas[3]) / 255.0;
+    let buffer_alpha = f64::from(buffer[3]) / 255.0;
+    let blend_alpha_f64 = buffer_alpha + canvas_alpha * (1.0 - buffer_alpha);
+    //value should be between 0 and 255, this truncates the fractional part
+    let blend_alpha = (blend_alpha_f64 * 255.0) as u8;
+
+    let mut rgb = [0u8; 3];
+    if blend_alpha != 0 {
+        for i in 0..3 {
+            let premultiplied = srgb_to_linear(buffer[i]) * buffer_alpha
+                + srgb_to_linear(canvas[i]) * canvas_alpha * (1.0 - buffer_alpha);
+            rgb[i] = linear_to_srgb(premultiplied / blend_alpha_f64);
+        }
+    }
+
+    [rgb[0], rgb[1], rgb[2], blend_alpha]
+}
+
 pub(crate) fn get_alpha_predictor(
     x: usize,
     y: usize,
     width: usize,
     filtering_method: FilteringMethod,
     image_slice: &[u8],
+) -> u8 {
+    predict_alpha(x, y, width, filtering_method, |index| {
+        image_slice[index * 4 + 3]
+    })
+}
+
+/// Like `get_alpha_predictor`, but for a plane holding only the alpha values
+pub(crate) fn get_alpha_plane_predictor(
+    x: usize,
+    y: usize,
+    width: usize,
+    filtering_method: FilteringMethod,
+    alpha_plane: &[u8],
+) -> u8 {
+    predict_alpha(x, y, width, filtering_method, |index| alpha_plane[index])
+}
+
+fn predict_alpha(
+    x: usize,
+    y: usize,
+    width: usize,
+    filtering_method: FilteringMethod,
+    alpha: impl Fn(usize) -> u8,
 ) -> u8 {
     match filtering_method {
         FilteringMethod::None => 0,
@@ -150,10 +218,10 @@ pub(crate) fn get_alpha_predictor(
                 0
             } else if x == 0 {
                 let index = (y - 1) * width + x;
-                image_slice[index * 4 + 3]
+                alpha(index)
             } else {
                 let index = y * width + x - 1;
-                image_slice[index * 4 + 3]
+                alpha(index)
             }
         }
         FilteringMethod::Vertical => {
@@ -161,10 +229,10 @@ pub(crate) fn get_alpha_predictor(
                 0
             } else if y == 0 {
                 let index = y * width + x - 1;
-                image_slice[index * 4 + 3]
+                alpha(index)
             } else {
                 let index = (y - 1) * width + x;
-                image_slice[index * 4 + 3]
+                alpha(index)
             }
         }
         FilteringMethod::Gradient => {
@@ -172,21 +240,21 @@ pub(crate) fn get_alpha_predictor(
                 (0, 0) => (0, 0, 0),
                 (0, y) => {
                     let above_index = (y - 1) * width + x;
-                    let val = image_slice[above_index * 4 + 3];
+                    let val = alpha(above_index);
                     (val, val, val)
                 }
                 (x, 0) => {
                     let before_index = y * width + x - 1;
-                    let val = image_slice[before_index * 4 + 3];
+                    let val = alpha(before_index);
                     (val, val, val)
                 }
                 (x, y) => {
                     let left_index = y * width + x - 1;
-                    let left = image_slice[left_index * 4 + 3];
+                    let left = alpha(left_index);
                     let top_index = (y - 1) * width + x;
-                    let top = image_slice[top_index * 4 + 3];
+                    let top = alpha(top_index);
                     let top_left_index = (y - 1) * width + x - 1;
-                    let top_left = image_slice[top_left_index * 4 + 3];
+                    let top_left = alpha(top_left_index);
 
                     (left, top, top_left)
                 }
@@ -272,17 +340,12 @@ pub(crate) enum FilteringMethod {
     Gradient,
 }
 
-pub(crate) fn read_alpha_chunk<R: Read>(
-    reader: &mut R,
-    width: u32,
-    height: u32,
-) -> Result<AlphaChunk, DecodingError> {
-    let info_byte = reader.read_u8()?;
-
+/// Validates the header byte of an ALPH chunk and splits it into its fields.
+pub(crate) fn read_alpha_info(info_byte: u8) -> Result<AlphaEncodingInfo, DecodingError> {
     let reserved = info_byte & 0b11000000;
     let preprocessing = (info_byte & 0b00110000) >> 4;
-    let filtering = (info_byte & 0b00001100) >> 2;
-    let compression = info_byte & 0b00000011;
+    let filtering_method = (info_byte & 0b00001100) >> 2;
+    let compression_method = info_byte & 0b00000011;
 
     if reserved != 0 {
         return Err(DecodingError::InfoBitsInvalid {
@@ -291,18 +354,38 @@ pub(crate) fn read_alpha_chunk<R: Read>(
         });
     }
 
-    let preprocessing = match preprocessing {
-        0 => false,
-        1 => true,
-        _ => {
-            return Err(DecodingError::InfoBitsInvalid {
-                name: "reserved",
-                value: preprocessing.into(),
-            })
-        }
-    };
+    if preprocessing > 1 {
+        return Err(DecodingError::InfoBitsInvalid {
+            name: "preprocessing",
+            value: preprocessing.into(),
+        });
+    }
+
+    if compression_method > 1 {
+        return Err(DecodingError::InfoBitsInvalid {
+            name: "lossless compression",
+            value: compression_method.into(),
+        });
+    }
 
-    let filtering_method = match filtering {
+    Ok(AlphaEncodingInfo {
+        filtering_method,
+        compression_method,
+        preprocessing,
+    })
+}
+
+pub(crate) fn read_alpha_chunk<R: Read>(
+    reader: &mut R,
+    width: u32,
+    height: u32,
+    huffman_strategy: HuffmanStrategy,
+    memory_limit: usize,
+) -> Result<AlphaChunk, DecodingError> {
+    let info = read_alpha_info(reader.read_u8()?)?;
+    let preprocessing = info.preprocessing == 1;
+    let lossless_compression = info.compression_method == 1;
+    let filtering_method = match info.filtering_method {
         0 => FilteringMethod::None,
         1 => FilteringMethod::Horizontal,
         2 => FilteringMethod::Vertical,
@@ -310,17 +393,6 @@ pub(crate) fn read_alpha_chunk<R: Read>(
         _ => unreachable!(),
     };
 
-    let lossless_compression = match compression {
-        0 => false,
-        1 => true,
-        _ => {
-            return Err(DecodingError::InfoBitsInvalid {
-                name: "lossless compression",
-                value: compression.into(),
-            })
-        }
-    };
-
     let mut framedata = Vec::new();
     reader.read_to_end(&mut framedata)?;
 
@@ -328,6 +400,8 @@ pub(crate) fn read_alpha_chunk<R: Read>(
         let cursor = io::Cursor::new(framedata);
 
         let mut decoder = LosslessDecoder::new(cursor);
+        decoder.set_huffman_strategy(huffman_strategy);
+        decoder.set_memory_limit(memory_limit);
         //this is a potential problem for large images; would require rewriting lossless decoder to
         //use u32 for width and height
         let width: u16 = width.try_into().map_err(|_| DecodingError::ImageTooLarge)?;
@@ -336,12 +410,28 @@ pub(crate) fn read_alpha_chunk<R: Read>(
             .map_err(|_| DecodingError::ImageTooLarge)?;
         let frame = decoder.decode_frame_implicit_dims(width, height)?;
 
-        let mut data = vec![0u8; usize::from(width) * usize::from(height)];
+        let expected = usize::from(width) * usize::from(height);
+        if frame.buf.len() != expected {
+            return Err(DecodingError::AlphaChunkSizeMismatch {
+                expected,
+                actual: frame.buf.len(),
+            });
+        }
+
+        let mut data = vec![0u8; expected];
 
         frame.fill_green(&mut data);
 
         data
     } else {
+        let expected = usize::try_from(u64::from(width) * u64::from(height))
+            .map_err(|_| DecodingError::ImageTooLarge)?;
+        if framedata.len() != expected {
+            return Err(DecodingError::AlphaChunkSizeMismatch {
+                expected,
+                actual: framedata.len(),
+            });
+        }
         framedata
     };
 
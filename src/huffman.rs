@@ -2,12 +2,10 @@ use std::convert::TryInto;
 
 use crate::decoder::DecodingError;
 
-use super::lossless::BitReader;
+use crate::entropy::{BitRead, LosslessBitReader};
 
 /// Rudimentary utility for reading Canonical Huffman Codes.
 /// Based off https://github.com/webmproject/libwebp/blob/7f8472a610b61ec780ef0a8873cd954ac512a505/src/utils/huffman.c
-///
-
 const MAX_ALLOWED_CODE_LENGTH: usize = 15;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -17,12 +15,23 @@ enum HuffmanTreeNode {
     Empty,
 }
 
+/// Number of bits resolved by a single lookup in the table built by `build_table`
+const TABLE_BITS: u8 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TableEntry {
+    Symbol(u16, u8), //symbol and the length of its code
+    Node(usize),     //node reached after TABLE_BITS bits, for longer codes
+    Invalid,         //no code starts with these bits
+}
+
 /// Huffman tree
 #[derive(Clone, Debug, Default)]
 pub(crate) struct HuffmanTree {
     tree: Vec<HuffmanTreeNode>,
     max_nodes: usize,
     num_nodes: usize,
+    table: Vec<TableEntry>,
 }
 
 impl HuffmanTree {
@@ -53,6 +62,7 @@ impl HuffmanTree {
             tree,
             max_nodes,
             num_nodes,
+            table: Vec::new(),
         };
 
         Ok(tree)
@@ -186,9 +196,49 @@ impl HuffmanTree {
         Ok(tree)
     }
 
+    /// Builds a lookup table that resolves the first `TABLE_BITS` bits of a code at once, so that
+    /// `read_symbol` only walks the tree for longer codes
+    pub(crate) fn build_table(&mut self) {
+        self.table = (0..1usize << TABLE_BITS)
+            .map(|bits| {
+                let mut index = 0;
+                for length in 0..TABLE_BITS {
+                    match self.tree[index] {
+                        HuffmanTreeNode::Branch(offset) => index += offset + ((bits >> length) & 1),
+                        HuffmanTreeNode::Leaf(symbol) => return TableEntry::Symbol(symbol, length),
+                        HuffmanTreeNode::Empty => return TableEntry::Invalid,
+                    }
+                }
+                match self.tree[index] {
+                    HuffmanTreeNode::Branch(_) => TableEntry::Node(index),
+                    HuffmanTreeNode::Leaf(symbol) => TableEntry::Symbol(symbol, TABLE_BITS),
+                    HuffmanTreeNode::Empty => TableEntry::Invalid,
+                }
+            })
+            .collect();
+    }
+
     /// Reads a symbol using the bitstream
-    pub(crate) fn read_symbol(&self, bit_reader: &mut BitReader) -> Result<u16, DecodingError> {
+    pub(crate) fn read_symbol(
+        &self,
+        bit_reader: &mut LosslessBitReader,
+    ) -> Result<u16, DecodingError> {
         let mut index = 0;
+
+        if !self.table.is_empty() {
+            match self.table[bit_reader.peek_u32(TABLE_BITS) as usize] {
+                TableEntry::Symbol(symbol, length) => {
+                    bit_reader.consume(length)?;
+                    return Ok(symbol);
+                }
+                TableEntry::Node(node) => {
+                    bit_reader.consume(TABLE_BITS)?;
+                    index = node;
+                }
+                TableEntry::Invalid => return Err(DecodingError::HuffmanError),
+            }
+        }
+
         let mut node = self.tree[index];
 
         while let HuffmanTreeNode::Branch(children_offset) = node {
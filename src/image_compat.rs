@@ -0,0 +1,101 @@
+//! Integration with the `image` crate, enabled by the `image` feature
+
+use std::io::{Read, Seek};
+
+use image::error::{DecodingError as ImageDecodingError, ImageFormatHint};
+use image::{AnimationDecoder, Delay, Frame, Frames, ImageError, ImageFormat, RgbaImage};
+
+use crate::decoder::{DecodingError, PixelFormat, RawImage, WebPDecoder};
+
+fn image_error(e: DecodingError) -> ImageError {
+    ImageError::Decoding(ImageDecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::WebP),
+        e,
+    ))
+}
+
+/// Yields one loop of the animation, composited onto the canvas, or a single frame for still
+/// images.
+impl<'a, R: Read + Seek + 'a> AnimationDecoder<'a> for WebPDecoder<R> {
+    fn into_frames(mut self) -> Frames<'a> {
+        let (width, height) = self.dimensions();
+        let mut buf = vec![0; self.output_buffer_size()];
+        let mut frames_left = self.num_frames();
+
+        Frames::new(Box::new(std::iter::from_fn(move || {
+            if frames_left == 0 {
+                return None;
+            }
+            frames_left -= 1;
+
+            let duration = if self.has_animation() {
+                match self.read_frame(&mut buf) {
+                    Ok(Some(duration)) => duration,
+                    Ok(None) => return None,
+                    Err(e) => {
+                        frames_left = 0;
+                        return Some(Err(image_error(e)));
+                    }
+                }
+            } else {
+                if let Err(e) = self.read_image(&mut buf) {
+                    return Some(Err(image_error(e)));
+                }
+                0
+            };
+
+            let rgba = match self.output_format() {
+                PixelFormat::Rgba8 => buf.clone(),
+                format => RawImage {
+                    width,
+                    height,
+                    format,
+                    data: buf.clone(),
+                }
+                .as_rgba()
+                .into_owned(),
+            };
+            let image = RgbaImage::from_raw(width, height, rgba).unwrap();
+            Some(Ok(Frame::from_parts(
+                image,
+                0,
+                0,
+                Delay::from_numer_denom_ms(duration, 1),
+            )))
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::AnimationDecoder;
+
+    use crate::decoder::tests::solid_animation;
+    use crate::WebPDecoder;
+
+    #[test]
+    fn collect_frames() {
+        let bytes = solid_animation(
+            1,
+            1,
+            [0; 4],
+            &[
+                (0, 0, 1, 1, 10, 0, [1, 2, 3, 255]),
+                (0, 0, 1, 1, 20, 0, [4, 5, 6, 255]),
+            ],
+        );
+        let decoder = WebPDecoder::new(Cursor::new(bytes)).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].delay().numer_denom_ms(), (20, 1));
+        assert_eq!(frames[1].buffer().as_raw(), &[4, 5, 6, 255]);
+
+        let bytes = std::fs::read("tests/images/1.webp").unwrap();
+        let decoder = WebPDecoder::new(Cursor::new(bytes)).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].buffer().dimensions(), (550, 368));
+    }
+}
@@ -1,15 +1,27 @@
 //! Decoding and Encoding of WebP Images
 
 #![forbid(unsafe_code)]
+#![cfg_attr(all(test, feature = "benchmarks"), feature(test))]
 
-pub use self::decoder::{DecodingError, WebPDecoder};
+pub use self::decoder::{
+    capabilities, quick_classify, AlphaEncodingInfo, BlendSpace, Capabilities, ChunkReport,
+    ChunkType, ColorSpace, DecodedImage, DecodingError, FrameInfo, FrameRect, Frames,
+    HuffmanStrategy, ImageStorage, ImageView, LoopCount, LosslessTransform, Orientation,
+    PixelFormat, PixelLayout, ProgressEvent, RawImage, SizePolicy, StructureReport, WebPClass,
+    WebPDecoder,
+};
 
 mod decoder;
+mod entropy;
 mod extended;
 mod huffman;
+#[cfg(feature = "image")]
+mod image_compat;
 mod loop_filter;
 mod lossless;
 mod lossless_transform;
 mod transform;
+#[cfg(feature = "simd")]
+mod yuv_simd;
 
 pub mod vp8;
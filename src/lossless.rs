@@ -3,16 +3,12 @@
 //! [Lossless spec](https://developers.google.com/speed/webp/docs/webp_lossless_bitstream_specification)
 //!
 
-use std::{
-    convert::TryFrom,
-    convert::TryInto,
-    io::Read,
-    ops::{AddAssign, Shl},
-};
+use std::{convert::TryFrom, convert::TryInto, io::Read};
 
 use byteorder::ReadBytesExt;
 
-use crate::decoder::DecodingError;
+use crate::decoder::{DecodingError, HuffmanStrategy, LosslessTransform};
+use crate::entropy::{BitRead, LosslessBitReader};
 
 use super::huffman::HuffmanTree;
 use super::lossless_transform::{add_pixels, TransformType};
@@ -66,10 +62,12 @@ const NUM_TRANSFORM_TYPES: usize = 4;
 #[derive(Debug)]
 pub(crate) struct LosslessDecoder<R> {
     r: R,
-    bit_reader: BitReader,
+    bit_reader: LosslessBitReader,
     frame: LosslessFrame,
     transforms: [Option<TransformType>; NUM_TRANSFORM_TYPES],
     transform_order: Vec<u8>,
+    huffman_strategy: HuffmanStrategy,
+    memory_limit: usize,
 }
 
 impl<R: Read> LosslessDecoder<R> {
@@ -77,15 +75,80 @@ impl<R: Read> LosslessDecoder<R> {
     pub(crate) fn new(r: R) -> LosslessDecoder<R> {
         LosslessDecoder {
             r,
-            bit_reader: BitReader::new(),
+            bit_reader: LosslessBitReader::new(),
             frame: Default::default(),
             transforms: [None, None, None, None],
             transform_order: Vec::new(),
+            huffman_strategy: HuffmanStrategy::default(),
+            memory_limit: usize::MAX,
         }
     }
 
+    /// Sets the maximum number of bytes that may be allocated at once for the image data, the
+    /// huffman codes and the color cache
+    pub(crate) fn set_memory_limit(&mut self, limit: usize) {
+        self.memory_limit = limit;
+    }
+
+    fn check_memory_limit(&self, bytes: usize) -> Result<(), DecodingError> {
+        if bytes > self.memory_limit {
+            return Err(DecodingError::MemoryLimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Sets how the huffman trees of the image are represented
+    pub(crate) fn set_huffman_strategy(&mut self, strategy: HuffmanStrategy) {
+        self.huffman_strategy = strategy;
+    }
+
     /// Reads the frame
     pub(crate) fn decode_frame(&mut self) -> Result<&LosslessFrame, DecodingError> {
+        self.read_header()?;
+
+        let mut data = self.decode_image_stream(self.frame.width, self.frame.height, true)?;
+
+        for &trans_index in self.transform_order.iter().rev() {
+            let trans = self.transforms[usize::from(trans_index)].as_ref().unwrap();
+            trans.apply_transform(&mut data, self.frame.width, self.frame.height)?;
+        }
+
+        self.frame.buf = data;
+        Ok(&self.frame)
+    }
+
+    /// Reads the transforms of the frame, in the order they appear in the bitstream, without
+    /// decoding the image data after them.
+    pub(crate) fn decode_transforms(&mut self) -> Result<Vec<LosslessTransform>, DecodingError> {
+        self.read_header()?;
+        self.read_transforms()?;
+
+        Ok(self
+            .transform_order
+            .iter()
+            .map(
+                |&index| match self.transforms[usize::from(index)].as_ref().unwrap() {
+                    TransformType::PredictorTransform { size_bits, .. } => {
+                        LosslessTransform::Predictor {
+                            size_bits: *size_bits,
+                        }
+                    }
+                    TransformType::ColorTransform { size_bits, .. } => LosslessTransform::Color {
+                        size_bits: *size_bits,
+                    },
+                    TransformType::SubtractGreen => LosslessTransform::SubtractGreen,
+                    TransformType::ColorIndexingTransform { table_size, .. } => {
+                        LosslessTransform::ColorIndexing {
+                            table_size: *table_size,
+                        }
+                    }
+                },
+            )
+            .collect())
+    }
+
+    /// Reads the signature and the image header, up to the transforms.
+    fn read_header(&mut self) -> Result<(), DecodingError> {
         let signature = self.r.read_u8()?;
 
         if signature != 0x2f {
@@ -107,15 +170,8 @@ impl<R: Read> LosslessDecoder<R> {
             return Err(DecodingError::VersionNumberInvalid(version_num));
         }
 
-        let mut data = self.decode_image_stream(self.frame.width, self.frame.height, true)?;
-
-        for &trans_index in self.transform_order.iter().rev() {
-            let trans = self.transforms[usize::from(trans_index)].as_ref().unwrap();
-            trans.apply_transform(&mut data, self.frame.width, self.frame.height)?;
-        }
-
-        self.frame.buf = data;
-        Ok(&self.frame)
+        // Reject huge images before decoding any of their transforms.
+        self.check_memory_limit(4 * usize::from(self.frame.width) * usize::from(self.frame.height))
     }
 
     //used for alpha data in extended decoding
@@ -130,6 +186,7 @@ impl<R: Read> LosslessDecoder<R> {
 
         self.frame.width = width;
         self.frame.height = height;
+        self.check_memory_limit(4 * usize::from(width) * usize::from(height))?;
 
         let mut data = self.decode_image_stream(self.frame.width, self.frame.height, true)?;
 
@@ -161,6 +218,9 @@ impl<R: Read> LosslessDecoder<R> {
 
         let color_cache_bits = self.read_color_cache()?;
 
+        if let Some(bits) = color_cache_bits {
+            self.check_memory_limit(4 << bits)?;
+        }
         let color_cache = color_cache_bits.map(|bits| {
             let size = 1 << bits;
             let cache = vec![0u32; size];
@@ -183,6 +243,11 @@ impl<R: Read> LosslessDecoder<R> {
         let mut xsize = self.frame.width;
 
         while self.bit_reader.read_bits::<u8>(1)? == 1 {
+            //each transform type may be used at most once, so there can't be more than four
+            if self.transform_order.len() == NUM_TRANSFORM_TYPES {
+                return Err(DecodingError::TransformError);
+            }
+
             let transform_type_val = self.bit_reader.read_bits::<u8>(2)?;
 
             if self.transforms[usize::from(transform_type_val)].is_some() {
@@ -229,6 +294,7 @@ impl<R: Read> LosslessDecoder<R> {
                     TransformType::SubtractGreen
                 }
                 3 => {
+                    // The table size is stored in 8 bits, so a palette has at most 256 entries.
                     let color_table_size = self.bit_reader.read_bits::<u16>(8)? + 1;
 
                     let mut color_map = self.decode_image_stream(color_table_size, 1, false)?;
@@ -261,7 +327,7 @@ impl<R: Read> LosslessDecoder<R> {
     }
 
     /// Adjusts the color map since it's subtraction coded
-    fn adjust_color_map(color_map: &mut Vec<u32>) {
+    fn adjust_color_map(color_map: &mut [u32]) {
         for i in 1..color_map.len() {
             color_map[i] = add_pixels(color_map[i], color_map[i - 1]);
         }
@@ -301,6 +367,9 @@ impl<R: Read> LosslessDecoder<R> {
             }
         }
 
+        self.check_memory_limit(
+            num_huff_groups as usize * std::mem::size_of::<HuffmanCodeGroup>(),
+        )?;
         let mut hufftree_groups = Vec::new();
 
         for _i in 0..num_huff_groups {
@@ -342,7 +411,7 @@ impl<R: Read> LosslessDecoder<R> {
     fn read_huffman_code(&mut self, alphabet_size: u16) -> Result<HuffmanTree, DecodingError> {
         let simple = self.bit_reader.read_bits::<u8>(1)? == 1;
 
-        if simple {
+        let mut tree = if simple {
             let num_symbols = self.bit_reader.read_bits::<u8>(1)? + 1;
 
             let mut code_lengths = vec![u16::from(num_symbols - 1)];
@@ -358,7 +427,7 @@ impl<R: Read> LosslessDecoder<R> {
                 codes.push(1);
             }
 
-            HuffmanTree::build_explicit(code_lengths, codes, symbols)
+            HuffmanTree::build_explicit(code_lengths, codes, symbols)?
         } else {
             let mut code_length_code_lengths = vec![0; CODE_LENGTH_CODES];
 
@@ -371,8 +440,13 @@ impl<R: Read> LosslessDecoder<R> {
             let new_code_lengths =
                 self.read_huffman_code_lengths(code_length_code_lengths, alphabet_size)?;
 
-            HuffmanTree::build_implicit(new_code_lengths)
+            HuffmanTree::build_implicit(new_code_lengths)?
+        };
+
+        if self.huffman_strategy == HuffmanStrategy::FastTable {
+            tree.build_table();
         }
+        Ok(tree)
     }
 
     /// Reads huffman code lengths
@@ -449,6 +523,7 @@ impl<R: Read> LosslessDecoder<R> {
         mut huffman_info: HuffmanInfo,
     ) -> Result<Vec<u32>, DecodingError> {
         let num_values = usize::from(width) * usize::from(height);
+        self.check_memory_limit(4 * num_values)?;
         let mut data = vec![0; num_values];
 
         let huff_index = huffman_info.get_huff_index(0, 0);
@@ -552,7 +627,7 @@ impl<R: Read> LosslessDecoder<R> {
 
     /// Gets the copy distance from the prefix code and bitstream
     fn get_copy_distance(
-        bit_reader: &mut BitReader,
+        bit_reader: &mut LosslessBitReader,
         prefix_code: u16,
     ) -> Result<usize, DecodingError> {
         if prefix_code < 4 {
@@ -622,6 +697,7 @@ impl ColorCache {
     }
 }
 
+#[cfg(any(test, not(feature = "buffered-entropy")))]
 #[derive(Debug, Clone)]
 pub(crate) struct BitReader {
     buf: Vec<u8>,
@@ -629,31 +705,49 @@ pub(crate) struct BitReader {
     bit_count: u8,
 }
 
+#[cfg(any(test, not(feature = "buffered-entropy")))]
 impl BitReader {
-    fn new() -> BitReader {
+    pub(crate) fn new() -> BitReader {
         BitReader {
             buf: Vec::new(),
             index: 0,
             bit_count: 0,
         }
     }
+}
 
+#[cfg(any(test, not(feature = "buffered-entropy")))]
+impl BitRead for BitReader {
     fn init(&mut self, buf: Vec<u8>) {
         self.buf = buf;
+        self.index = 0;
+        self.bit_count = 0;
+    }
+
+    fn peek_u32(&mut self, num: u8) -> u32 {
+        let position = self.index * 8 + usize::from(self.bit_count);
+        let mut value = 0;
+
+        for i in 0..num {
+            let bit = position + usize::from(i);
+            match self.buf.get(bit / 8) {
+                Some(byte) => value |= u32::from((byte >> (bit % 8)) & 1) << i,
+                None => break,
+            }
+        }
+
+        value
     }
 
-    pub(crate) fn read_bits<T>(&mut self, num: u8) -> Result<T, DecodingError>
-    where
-        T: num_traits::Unsigned + Shl<u8, Output = T> + AddAssign<T> + From<bool>,
-    {
-        let mut value: T = T::zero();
+    fn read_u32(&mut self, num: u8) -> Result<u32, DecodingError> {
+        let mut value = 0;
 
         for i in 0..num {
             if self.buf.len() <= self.index {
                 return Err(DecodingError::BitStreamError);
             }
             let bit_true = self.buf[self.index] & (1 << self.bit_count) != 0;
-            value += T::from(bit_true) << i;
+            value += u32::from(bit_true) << i;
             self.bit_count = if self.bit_count == 7 {
                 self.index += 1;
                 0
@@ -685,6 +779,38 @@ impl LosslessFrame {
         }
     }
 
+    /// Fills an rgb buffer from the lossless decoding, dropping the alpha values
+    pub(crate) fn fill_rgb(&self, buf: &mut [u8]) {
+        for (&argb_val, chunk) in self.buf.iter().zip(buf.chunks_exact_mut(3)) {
+            chunk[0] = ((argb_val >> 16) & 0xff).try_into().unwrap();
+            chunk[1] = ((argb_val >> 8) & 0xff).try_into().unwrap();
+            chunk[2] = (argb_val & 0xff).try_into().unwrap();
+        }
+    }
+
+    /// Fills a buffer with the rgba values of the rows starting at `start_row`, one row per
+    /// `width * 4` bytes of `buf`
+    pub(crate) fn fill_rgba_rows(&self, start_row: usize, buf: &mut [u8]) {
+        let start = start_row * usize::from(self.width);
+        for (&argb_val, chunk) in self.buf[start..].iter().zip(buf.chunks_exact_mut(4)) {
+            chunk[0] = ((argb_val >> 16) & 0xff).try_into().unwrap();
+            chunk[1] = ((argb_val >> 8) & 0xff).try_into().unwrap();
+            chunk[2] = (argb_val & 0xff).try_into().unwrap();
+            chunk[3] = ((argb_val >> 24) & 0xff).try_into().unwrap();
+        }
+    }
+
+    /// Fills a buffer with the rgb values of the rows starting at `start_row`, one row per
+    /// `width * 3` bytes of `buf`
+    pub(crate) fn fill_rgb_rows(&self, start_row: usize, buf: &mut [u8]) {
+        let start = start_row * usize::from(self.width);
+        for (&argb_val, chunk) in self.buf[start..].iter().zip(buf.chunks_exact_mut(3)) {
+            chunk[0] = ((argb_val >> 16) & 0xff).try_into().unwrap();
+            chunk[1] = ((argb_val >> 8) & 0xff).try_into().unwrap();
+            chunk[2] = (argb_val & 0xff).try_into().unwrap();
+        }
+    }
+
     /// Fills a buffer with just the green values from the lossless decoding
     /// Used in extended alpha decoding
     pub(crate) fn fill_green(&self, buf: &mut [u8]) {
@@ -695,9 +821,240 @@ impl LosslessFrame {
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
+
+    use std::io::Cursor;
+
+    use super::{BitReader, LosslessDecoder};
+    use crate::decoder::DecodingError;
+    use crate::entropy::BitRead;
+    use crate::lossless_transform::TransformType;
+
+    /// Writes values LSB-first, matching the order in which `BitReader` consumes them.
+    #[derive(Default)]
+    struct BitWriter {
+        buf: Vec<u8>,
+        bit_count: u8,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u32, num: u8) {
+            for i in 0..num {
+                if self.bit_count == 0 {
+                    self.buf.push(0);
+                }
+                if value & (1 << i) != 0 {
+                    *self.buf.last_mut().unwrap() |= 1 << self.bit_count;
+                }
+                self.bit_count = (self.bit_count + 1) % 8;
+            }
+        }
+
+        /// Writes the VP8L signature and image header.
+        fn write_header(&mut self, width: u16, height: u16) {
+            self.write(0x2f, 8);
+            self.write(u32::from(width - 1), 14);
+            self.write(u32::from(height - 1), 14);
+            self.write(0, 1);
+            self.write(0, 3);
+        }
+
+        /// Writes five simple single-symbol codes, so that every pixel decodes to the given green,
+        /// red, blue and alpha symbols without consuming any bits.
+        fn write_single_symbol_codes(&mut self, symbols: [u8; 4]) {
+            for symbol in symbols.into_iter().chain([0]) {
+                self.write(1, 1);
+                self.write(0, 1);
+                self.write(1, 1);
+                self.write(u32::from(symbol), 8);
+            }
+        }
+    }
 
-    use super::BitReader;
+    /// Encodes a VP8L bitstream in which every pixel has the color `rgba`.
+    pub(crate) fn solid_color_image(width: u16, height: u16, rgba: [u8; 4]) -> Vec<u8> {
+        let [r, g, b, a] = rgba;
+        let mut w = BitWriter::default();
+        w.write_header(width, height);
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write_single_symbol_codes([g, r, b, a]);
+        w.buf
+    }
+
+    #[test]
+    fn simple_codes() {
+        let mut w = BitWriter::default();
+        w.write_header(3, 1);
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write(0, 1);
+
+        // A simple green code with two symbols, the first of which is stored in a single bit.
+        w.write(1, 1);
+        w.write(1, 1);
+        w.write(0, 1);
+        w.write(1, 1);
+        w.write(200, 8);
+        // Single symbol red, blue, alpha and distance codes, the first stored in a single bit.
+        w.write(1, 1);
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write(1, 1);
+        for symbol in [3, 255, 0] {
+            w.write(1, 1);
+            w.write(0, 1);
+            w.write(1, 1);
+            w.write(symbol, 8);
+        }
+
+        // Green symbols for each pixel, one bit each.
+        w.write(0b101, 3);
+
+        let mut decoder = LosslessDecoder::new(Cursor::new(w.buf));
+        let frame = decoder.decode_frame().unwrap();
+        assert_eq!(frame.buf, [0xff01c803, 0xff010103, 0xff01c803]);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut w = BitWriter::default();
+        w.write_header(16383, 16383);
+        w.write(0, 32);
+
+        let mut decoder = LosslessDecoder::new(Cursor::new(w.buf));
+        decoder.set_memory_limit(1 << 20);
+        assert!(matches!(
+            decoder.decode_frame(),
+            Err(DecodingError::MemoryLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn smallest_transform_block_size() {
+        let mut w = BitWriter::default();
+        w.write_header(9, 1);
+
+        // A predictor transform with the smallest possible block size. The 3 bits store the
+        // block size minus 2, so it can't be less than 4x4 pixels.
+        w.write(1, 1);
+        w.write(0, 2);
+        w.write(0, 3);
+        w.write(0, 1);
+        w.write_single_symbol_codes([0, 0, 0, 0]);
+        w.write(0, 1);
+
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write_single_symbol_codes([0, 0, 0, 0]);
+
+        let mut decoder = LosslessDecoder::new(Cursor::new(w.buf));
+        let frame = decoder.decode_frame().unwrap();
+        // The top row is predicted from black and then from the left neighbor.
+        assert_eq!(frame.buf, [0xff000000; 9]);
+        assert!(matches!(
+            &decoder.transforms[0],
+            Some(TransformType::PredictorTransform {
+                size_bits: 2,
+                predictor_data,
+            }) if predictor_data.len() == 3
+        ));
+    }
+
+    #[test]
+    fn too_many_transforms() {
+        let mut w = BitWriter::default();
+        w.write_header(1, 1);
+
+        // subtract green
+        w.write(1, 1);
+        w.write(2, 2);
+        // predictor and color transforms, each with a 1x1 sub-image
+        for transform_type in [0, 1] {
+            w.write(1, 1);
+            w.write(transform_type, 2);
+            w.write(7, 3);
+            w.write(0, 1);
+            w.write_single_symbol_codes([0, 0, 0, 0]);
+        }
+        // color indexing with a single entry
+        w.write(1, 1);
+        w.write(3, 2);
+        w.write(0, 8);
+        w.write(0, 1);
+        w.write_single_symbol_codes([0, 0, 0, 0]);
+        // a fifth transform
+        w.write(1, 1);
+        w.write(2, 2);
+
+        let mut decoder = LosslessDecoder::new(Cursor::new(w.buf));
+        assert!(matches!(
+            decoder.decode_frame(),
+            Err(DecodingError::TransformError)
+        ));
+    }
+
+    #[test]
+    fn duplicate_transform() {
+        let mut w = BitWriter::default();
+        w.write_header(1, 1);
+        w.write(1, 1);
+        w.write(2, 2);
+        w.write(1, 1);
+        w.write(2, 2);
+
+        let mut decoder = LosslessDecoder::new(Cursor::new(w.buf));
+        assert!(matches!(
+            decoder.decode_frame(),
+            Err(DecodingError::TransformError)
+        ));
+    }
+
+    #[test]
+    fn color_indexing_out_of_range_index() {
+        let mut w = BitWriter::default();
+        w.write_header(4, 1);
+
+        // Color indexing transform with a 3 entry palette. Every entry is subtraction coded as
+        // 0x01010101.
+        w.write(1, 1);
+        w.write(3, 2);
+        w.write(2, 8);
+        w.write(0, 1);
+        w.write_single_symbol_codes([1, 1, 1, 1]);
+        w.write(0, 1);
+
+        // Four 2-bit indices packed into one green value: 0, 1, 2, and the out-of-range 3.
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write_single_symbol_codes([0b11_10_01_00, 0, 0, 0]);
+
+        let mut decoder = LosslessDecoder::new(Cursor::new(w.buf));
+        let frame = decoder.decode_frame().unwrap();
+        assert_eq!(frame.buf, [0x01010101, 0x02020202, 0x03030303, 0]);
+    }
+
+    #[test]
+    fn color_indexing_max_table_size() {
+        let mut w = BitWriter::default();
+        w.write_header(1, 1);
+
+        w.write(1, 1);
+        w.write(3, 2);
+        w.write(255, 8);
+        w.write(0, 1);
+        w.write_single_symbol_codes([0, 0, 1, 0]);
+        w.write(0, 1);
+
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write_single_symbol_codes([254, 0, 0, 0]);
+
+        let mut decoder = LosslessDecoder::new(Cursor::new(w.buf));
+        let frame = decoder.decode_frame().unwrap();
+        assert_eq!(frame.buf, [0x000000ff]);
+    }
 
     #[test]
     fn bit_read_test() {
@@ -6,10 +6,14 @@
 //! It decodes Keyframes only.
 //! VP8 is the underpinning of the WebP image format
 //!
+//! The WebP container requires every VP8 bitstream, including those in animation frames, to be a
+//! single keyframe. Animations are built by compositing independent frames rather than with VP8
+//! inter prediction, so the last/golden/altref reference buffers are never needed.
+//!
 //! # Related Links
 //! * [rfc-6386](http://tools.ietf.org/html/rfc6386) - The VP8 Data Format and Decoding Guide
 //! * [VP8.pdf](http://static.googleusercontent.com/media/research.google.com/en//pubs/archive/37073.pdf) - An overview of
-//! of the VP8 format
+//!   of the VP8 format
 //!
 
 use byteorder::{LittleEndian, ReadBytesExt};
@@ -17,13 +21,18 @@ use num_traits::clamp;
 use std::cmp;
 use std::convert::TryInto;
 use std::default::Default;
-use std::io::Read;
+use std::io::{self, Read};
+use std::mem;
 
 use crate::decoder::DecodingError;
+use crate::entropy::{BoolDecoder, Vp8BoolReader};
 
 use super::loop_filter;
 use super::transform;
 
+/// The default for [`Vp8Decoder::set_max_overread_bytes`]
+pub const DEFAULT_MAX_OVERREAD_BYTES: usize = 32;
+
 const MAX_SEGMENTS: usize = 4;
 const NUM_DCT_TOKENS: usize = 12;
 
@@ -667,15 +676,19 @@ static AC_QUANT: [i16; 128] = [
 
 static ZIGZAG: [u8; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
 
-struct BoolReader {
+#[cfg(any(test, not(feature = "buffered-entropy")))]
+pub(crate) struct BoolReader {
     buf: Vec<u8>,
     index: usize,
+    /// The number of zero bytes shifted in after reaching the end of `buf`
+    padding_bytes: usize,
 
     range: u32,
     value: u32,
     bit_count: u8,
 }
 
+#[cfg(any(test, not(feature = "buffered-entropy")))]
 impl BoolReader {
     pub(crate) fn new() -> BoolReader {
         BoolReader {
@@ -684,10 +697,14 @@ impl BoolReader {
             value: 0,
             bit_count: 0,
             index: 0,
+            padding_bytes: 0,
         }
     }
+}
 
-    pub(crate) fn init(&mut self, buf: Vec<u8>) -> Result<(), DecodingError> {
+#[cfg(any(test, not(feature = "buffered-entropy")))]
+impl BoolDecoder for BoolReader {
+    fn init(&mut self, buf: Vec<u8>) -> Result<(), DecodingError> {
         if buf.len() < 2 {
             return Err(DecodingError::NotEnoughInitData);
         }
@@ -696,13 +713,14 @@ impl BoolReader {
         // Direct access safe, since length has just been validated.
         self.value = (u32::from(self.buf[0]) << 8) | u32::from(self.buf[1]);
         self.index = 2;
+        self.padding_bytes = 0;
         self.range = 255;
         self.bit_count = 0;
 
         Ok(())
     }
 
-    pub(crate) fn read_bool(&mut self, probability: u8) -> bool {
+    fn read_bool(&mut self, probability: u8) -> bool {
         let split = 1 + (((self.range - 1) * u32::from(probability)) >> 8);
         let bigsplit = split << 8;
 
@@ -728,6 +746,8 @@ impl BoolReader {
                 if self.index < self.buf.len() {
                     self.value |= u32::from(self.buf[self.index]);
                     self.index += 1;
+                } else {
+                    self.padding_bytes += 1;
                 }
             }
         }
@@ -735,47 +755,11 @@ impl BoolReader {
         retval
     }
 
-    pub(crate) fn read_literal(&mut self, n: u8) -> u8 {
-        let mut v = 0u8;
-        let mut n = n;
-
-        while n != 0 {
-            v = (v << 1) + self.read_bool(128u8) as u8;
-            n -= 1;
-        }
-
-        v
-    }
-
-    pub(crate) fn read_magnitude_and_sign(&mut self, n: u8) -> i32 {
-        let magnitude = self.read_literal(n);
-        let sign = self.read_literal(1);
-
-        if sign == 1 {
-            -i32::from(magnitude)
-        } else {
-            i32::from(magnitude)
-        }
-    }
-
-    pub(crate) fn read_with_tree(&mut self, tree: &[i8], probs: &[Prob], start: isize) -> i8 {
-        let mut index = start;
-
-        loop {
-            let a = self.read_bool(probs[index as usize >> 1]);
-            let b = index + a as isize;
-            index = tree[b as usize] as isize;
-
-            if index <= 0 {
-                break;
-            }
-        }
-
-        -index as i8
-    }
-
-    pub(crate) fn read_flag(&mut self) -> bool {
-        0 != self.read_literal(1)
+    fn overread_bits(&self) -> usize {
+        // `value` holds 16 bits, starting at the decoding window.
+        let loaded = self.index + self.padding_bytes;
+        let position = 8 * (loaded - 2) + usize::from(self.bit_count);
+        (position + 8).saturating_sub(8 * self.buf.len())
     }
 }
 
@@ -789,6 +773,38 @@ struct MacroBlock {
     coeffs_skipped: bool,
 }
 
+/// The loop filter selected by a VP8 frame header, as defined by Section 9.6 of the VP8
+/// Specification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    /// The normal loop filter
+    Normal,
+    /// The simple loop filter, which only filters luma edges
+    Simple,
+}
+
+/// The fields of a VP8 frame header that describe how the frame was encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vp8Header {
+    /// The version number from the frame tag, from 0 to 3
+    pub profile: u8,
+
+    /// The loop filter used for the frame
+    pub filter_type: FilterType,
+
+    /// The loop filter level for the frame, from 0 to 63. Zero disables the loop filter.
+    pub filter_level: u8,
+
+    /// Indicates whether the macroblocks are assigned to segments
+    pub segmentation_enabled: bool,
+
+    /// The width of the frame
+    pub width: u16,
+
+    /// The height of the frame
+    pub height: u16,
+}
+
 /// A Representation of the last decoded video frame
 #[derive(Default, Debug, Clone)]
 pub struct Frame {
@@ -820,6 +836,15 @@ pub struct Frame {
     /// of the VP8 Specification
     pub pixel_type: u8,
 
+    // Section 9.1
+    /// The horizontal upscaling factor of the frame as defined by Section 9.1
+    /// of the VP8 Specification. Zero means no upscaling.
+    pub horizontal_scale: u8,
+
+    /// The vertical upscaling factor of the frame as defined by Section 9.1
+    /// of the VP8 Specification. Zero means no upscaling.
+    pub vertical_scale: u8,
+
     // Section 9.4 and 15
     filter_type: bool, //if true uses simple filter // if false uses normal filter
     filter_level: u8,
@@ -838,37 +863,58 @@ impl Frame {
 
     /// Fills an rgb buffer with the image
     pub(crate) fn fill_rgb(&self, buf: &mut [u8]) {
-        for (index, rgb_chunk) in (0..self.ybuf.len()).zip(buf.chunks_exact_mut(3)) {
-            let y = index / self.width as usize;
-            let x = index % self.width as usize;
-            let chroma_index = self.chroma_width() as usize * (y / 2) + x / 2;
-
-            Frame::fill_single(
-                self.ybuf[index],
-                self.ubuf[chroma_index],
-                self.vbuf[chroma_index],
-                rgb_chunk,
-            );
-        }
+        self.fill_pixels::<3>(buf);
     }
 
     /// Fills an rgba buffer by skipping the alpha values
     pub(crate) fn fill_rgba(&self, buf: &mut [u8]) {
-        for (index, rgba_chunk) in (0..self.ybuf.len()).zip(buf.chunks_exact_mut(4)) {
-            let y = index / self.width as usize;
-            let x = index % self.width as usize;
-            let chroma_index = self.chroma_width() as usize * (y / 2) + x / 2;
-
-            Frame::fill_single(
-                self.ybuf[index],
-                self.ubuf[chroma_index],
-                self.vbuf[chroma_index],
-                rgba_chunk,
-            );
+        self.fill_pixels::<4>(buf);
+    }
+
+    /// Fills a buffer of `width * 3` bytes with row `y` of the image
+    pub(crate) fn fill_rgb_row(&self, y: usize, row: &mut [u8]) {
+        self.fill_row::<3>(y, row);
+    }
+
+    /// Fills a buffer of `width * 4` bytes with row `y` of the image, skipping the alpha values
+    pub(crate) fn fill_rgba_row(&self, y: usize, row: &mut [u8]) {
+        self.fill_row::<4>(y, row);
+    }
+
+    /// Converts the image row by row, writing the RGB values of each pixel to the first three of
+    /// its `BPP` bytes
+    fn fill_pixels<const BPP: usize>(&self, buf: &mut [u8]) {
+        let width = usize::from(self.width);
+        if width == 0 {
+            return;
+        }
+
+        for (y, row) in buf.chunks_exact_mut(width * BPP).enumerate() {
+            self.fill_row::<BPP>(y, row);
         }
     }
 
-    fn fill_single(y: u8, u: u8, v: u8, rgb: &mut [u8]) {
+    /// Converts row `y` of the image like `fill_pixels`
+    fn fill_row<const BPP: usize>(&self, y: usize, row: &mut [u8]) {
+        let width = usize::from(self.width);
+        let chroma_width = usize::from(self.chroma_width());
+
+        let y_row = &self.ybuf[y * width..][..width];
+        let chroma_start = chroma_width * (y / 2);
+        let u_row = &self.ubuf[chroma_start..][..chroma_width];
+        let v_row = &self.vbuf[chroma_start..][..chroma_width];
+
+        #[cfg(feature = "simd")]
+        let converted = crate::yuv_simd::fill_row::<BPP>(y_row, u_row, v_row, row);
+        #[cfg(not(feature = "simd"))]
+        let converted = 0;
+
+        for (x, pixel) in row.chunks_exact_mut(BPP).enumerate().skip(converted) {
+            Frame::fill_single(y_row[x], u_row[x / 2], v_row[x / 2], pixel);
+        }
+    }
+
+    pub(crate) fn fill_single(y: u8, u: u8, v: u8, rgb: &mut [u8]) {
         // // Conversion values from https://docs.microsoft.com/en-us/windows/win32/medfound/recommended-8-bit-yuv-formats-for-video-rendering#converting-8-bit-yuv-to-rgb888
         // let c: i32 = i32::from(y) - 16;
         // let d: i32 = i32::from(u) - 128;
@@ -884,7 +930,8 @@ impl Frame {
         //     .unwrap();
 
         // Based on [src/dsp/yuv.h](https://github.com/webmproject/libwebp/blob/8534f53960befac04c9631e6e50d21dcb42dfeaf/src/dsp/yuv.h#L79)
-        // from the libwebp source.
+        // from the libwebp source. Only integer arithmetic is used, so the output is bit exact on
+        // every platform.
         const YUV_FIX2: i32 = 6;
         const YUV_MASK2: i32 = (256 << YUV_FIX2) - 1;
 
@@ -930,12 +977,23 @@ struct Segment {
     loopfilter_level: i8,
 }
 
+/// Allocations of a [`Vp8Decoder`] that a later decoder can reuse instead of allocating its own.
+#[derive(Default)]
+pub(crate) struct Vp8Buffers {
+    ybuf: Vec<u8>,
+    ubuf: Vec<u8>,
+    vbuf: Vec<u8>,
+    macroblocks: Vec<MacroBlock>,
+    top: Vec<MacroBlock>,
+    top_border: Vec<u8>,
+}
+
 /// VP8 Decoder
 ///
 /// Only decodes keyframes
 pub struct Vp8Decoder<R> {
     r: R,
-    b: BoolReader,
+    b: Vp8BoolReader,
 
     mbwidth: u16,
     mbheight: u16,
@@ -950,7 +1008,7 @@ pub struct Vp8Decoder<R> {
     ref_delta: [i32; 4],
     mode_delta: [i32; 4],
 
-    partitions: [BoolReader; 8],
+    partitions: [Vp8BoolReader; 8],
     num_partitions: u8,
 
     segment_tree_probs: [Prob; 3],
@@ -967,6 +1025,9 @@ pub struct Vp8Decoder<R> {
 
     top_border: Vec<u8>,
     left_border: Vec<u8>,
+
+    max_overread_bytes: usize,
+    memory_limit: usize,
 }
 
 impl<R: Read> Vp8Decoder<R> {
@@ -979,7 +1040,7 @@ impl<R: Read> Vp8Decoder<R> {
 
         Vp8Decoder {
             r,
-            b: BoolReader::new(),
+            b: Vp8BoolReader::new(),
 
             mbwidth: 0,
             mbheight: 0,
@@ -994,14 +1055,14 @@ impl<R: Read> Vp8Decoder<R> {
             mode_delta: [0; 4],
 
             partitions: [
-                BoolReader::new(),
-                BoolReader::new(),
-                BoolReader::new(),
-                BoolReader::new(),
-                BoolReader::new(),
-                BoolReader::new(),
-                BoolReader::new(),
-                BoolReader::new(),
+                Vp8BoolReader::new(),
+                Vp8BoolReader::new(),
+                Vp8BoolReader::new(),
+                Vp8BoolReader::new(),
+                Vp8BoolReader::new(),
+                Vp8BoolReader::new(),
+                Vp8BoolReader::new(),
+                Vp8BoolReader::new(),
             ],
 
             num_partitions: 1,
@@ -1020,9 +1081,56 @@ impl<R: Read> Vp8Decoder<R> {
 
             top_border: Vec::new(),
             left_border: Vec::new(),
+
+            max_overread_bytes: DEFAULT_MAX_OVERREAD_BYTES,
+            memory_limit: usize::MAX,
+        }
+    }
+
+    /// Creates a new decoder that decodes into the allocations left behind by a previous one.
+    pub(crate) fn with_buffers(r: R, buffers: Vp8Buffers) -> Vp8Decoder<R> {
+        let mut decoder = Self::new(r);
+        decoder.frame.ybuf = buffers.ybuf;
+        decoder.frame.ubuf = buffers.ubuf;
+        decoder.frame.vbuf = buffers.vbuf;
+        decoder.macroblocks = buffers.macroblocks;
+        decoder.top = buffers.top;
+        decoder.top_border = buffers.top_border;
+        decoder
+    }
+
+    /// Returns the allocations of the decoder for use by the next one.
+    pub(crate) fn into_buffers(self) -> Vp8Buffers {
+        Vp8Buffers {
+            ybuf: self.frame.ybuf,
+            ubuf: self.frame.ubuf,
+            vbuf: self.frame.vbuf,
+            macroblocks: self.macroblocks,
+            top: self.top,
+            top_border: self.top_border,
         }
     }
 
+    /// Sets the maximum number of bytes the decoder may allocate for the planes and macroblock
+    /// state of a frame.
+    ///
+    /// Frames that would need more fail with [`DecodingError::MemoryLimitExceeded`] before any of
+    /// it is allocated. Defaults to no limit.
+    pub fn set_memory_limit(&mut self, limit: usize) {
+        self.memory_limit = limit;
+    }
+
+    /// Sets how many bytes the decoder may read past the end of a partition before giving up.
+    ///
+    /// Like libwebp, the decoder reads zeros past the end of the data, so an image that is cut
+    /// off slightly still decodes, with the damage limited to its last few macroblocks. Once
+    /// more than `bytes` have been read past the end, decoding fails with an
+    /// [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof) error instead. Defaults to
+    /// [`DEFAULT_MAX_OVERREAD_BYTES`].
+    pub fn set_max_overread_bytes(&mut self, bytes: usize) {
+        self.max_overread_bytes = bytes;
+    }
+
     fn update_token_probabilities(&mut self) {
         for (i, is) in COEFF_UPDATE_PROBS.iter().enumerate() {
             for (j, js) in is.iter().enumerate() {
@@ -1200,7 +1308,9 @@ impl<R: Read> Vp8Decoder<R> {
         }
     }
 
-    fn read_frame_header(&mut self) -> Result<(), DecodingError> {
+    /// Reads the frame tag and, for keyframes, the start code and dimensions. Returns the size of
+    /// the first partition.
+    fn read_frame_tag(&mut self) -> Result<u32, DecodingError> {
         let tag = self.r.read_u24::<LittleEndian>()?;
 
         self.frame.keyframe = tag & 1 == 0;
@@ -1222,24 +1332,18 @@ impl<R: Read> Vp8Decoder<R> {
 
             self.frame.width = w & 0x3FFF;
             self.frame.height = h & 0x3FFF;
-
-            self.top = init_top_macroblocks(self.frame.width as usize);
-            // Almost always the first macro block, except when non exists (i.e. `width == 0`)
-            self.left = self.top.get(0).cloned().unwrap_or_default();
-
-            self.mbwidth = (self.frame.width + 15) / 16;
-            self.mbheight = (self.frame.height + 15) / 16;
-
-            self.frame.ybuf = vec![0u8; self.frame.width as usize * self.frame.height as usize];
-            self.frame.ubuf =
-                vec![0u8; self.frame.chroma_width() as usize * self.frame.chroma_height() as usize];
-            self.frame.vbuf =
-                vec![0u8; self.frame.chroma_width() as usize * self.frame.chroma_height() as usize];
-
-            self.top_border = vec![127u8; self.frame.width as usize + 4 + 16];
-            self.left_border = vec![129u8; 1 + 16];
+            self.frame.horizontal_scale = (w >> 14) as u8;
+            self.frame.vertical_scale = (h >> 14) as u8;
         }
 
+        Ok(first_partition_size)
+    }
+
+    /// Reads the first partition and the frame header fields up to the loop filter settings.
+    fn read_first_partition_header(
+        &mut self,
+        first_partition_size: u32,
+    ) -> Result<(), DecodingError> {
         let mut buf = vec![0; first_partition_size as usize];
         self.r.read_exact(&mut buf)?;
 
@@ -1263,6 +1367,70 @@ impl<R: Read> Vp8Decoder<R> {
         self.frame.filter_type = self.b.read_flag();
         self.frame.filter_level = self.b.read_literal(6);
         self.frame.sharpness_level = self.b.read_literal(3);
+        Ok(())
+    }
+
+    /// Reads the frame header without allocating the frame buffers or decoding any macroblocks.
+    ///
+    /// Only the fields up to the loop filter settings are read, so the data of the frame does
+    /// not have to be present in full.
+    pub fn decode_frame_header(&mut self) -> Result<Vp8Header, DecodingError> {
+        let first_partition_size = self.read_frame_tag()?;
+        if !self.frame.keyframe {
+            return Err(DecodingError::NonKeyframe);
+        }
+        self.read_first_partition_header(first_partition_size)?;
+
+        Ok(Vp8Header {
+            profile: self.frame.version,
+            filter_type: if self.frame.filter_type {
+                FilterType::Simple
+            } else {
+                FilterType::Normal
+            },
+            filter_level: self.frame.filter_level,
+            segmentation_enabled: self.segments_enabled,
+            width: self.frame.width,
+            height: self.frame.height,
+        })
+    }
+
+    fn read_frame_header(&mut self) -> Result<(), DecodingError> {
+        let first_partition_size = self.read_frame_tag()?;
+
+        if self.frame.keyframe {
+            let luma_size = usize::from(self.frame.width) * usize::from(self.frame.height);
+            let chroma_size =
+                usize::from(self.frame.chroma_width()) * usize::from(self.frame.chroma_height());
+            let mbwidth = usize::from((self.frame.width + 15) / 16);
+            let mbheight = usize::from((self.frame.height + 15) / 16);
+            // `macroblocks` holds every macroblock of the frame, `top` one row of them.
+            let macroblocks_size = (mbwidth * mbheight + mbwidth) * mem::size_of::<MacroBlock>();
+            if luma_size + 2 * chroma_size + macroblocks_size > self.memory_limit {
+                return Err(DecodingError::MemoryLimitExceeded);
+            }
+
+            init_top_macroblocks(&mut self.top, self.frame.width as usize);
+            // Almost always the first macro block, except when non exists (i.e. `width == 0`)
+            self.left = self.top.first().cloned().unwrap_or_default();
+
+            self.mbwidth = (self.frame.width + 15) / 16;
+            self.mbheight = (self.frame.height + 15) / 16;
+            self.macroblocks.clear();
+
+            refill(&mut self.frame.ybuf, luma_size, 0);
+            refill(&mut self.frame.ubuf, chroma_size, 0);
+            refill(&mut self.frame.vbuf, chroma_size, 0);
+
+            refill(
+                &mut self.top_border,
+                self.frame.width as usize + 4 + 16,
+                127,
+            );
+            self.left_border = vec![129u8; 1 + 16];
+        }
+
+        self.read_first_partition_header(first_partition_size)?;
 
         let lf_adjust_enable = self.b.read_flag();
         if lf_adjust_enable {
@@ -1278,9 +1446,7 @@ impl<R: Read> Vp8Decoder<R> {
         if !self.frame.keyframe {
             // 9.7 refresh golden frame and altref frame
             // FIXME: support this?
-            return Err(DecodingError::UnsupportedFeature(
-                "Non-keyframe frames".to_owned(),
-            ));
+            return Err(DecodingError::NonKeyframe);
         } else {
             // Refresh entropy probs ?????
             let _ = self.b.read_literal(1);
@@ -1300,9 +1466,7 @@ impl<R: Read> Vp8Decoder<R> {
             self.prob_intra = 0;
 
             // FIXME: support this?
-            return Err(DecodingError::UnsupportedFeature(
-                "Non-keyframe frames".to_owned(),
-            ));
+            return Err(DecodingError::NonKeyframe);
         } else {
             // Reset motion vectors
         }
@@ -1320,8 +1484,8 @@ impl<R: Read> Vp8Decoder<R> {
                 as u8;
         };
 
-        mb.coeffs_skipped = if self.prob_skip_false.is_some() {
-            self.b.read_bool(*self.prob_skip_false.as_ref().unwrap())
+        mb.coeffs_skipped = if let Some(prob) = self.prob_skip_false {
+            self.b.read_bool(prob)
         } else {
             false
         };
@@ -2036,6 +2200,16 @@ impl<R: Read> Vp8Decoder<R> {
                     [0i32; 384]
                 };
 
+                let max_overread_bits = self.max_overread_bytes.saturating_mul(8);
+                if self.b.overread_bits() > max_overread_bits
+                    || self.partitions[p].overread_bits() > max_overread_bits
+                {
+                    return Err(DecodingError::IoError(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "VP8 partition is truncated",
+                    )));
+                }
+
                 self.intra_predict_luma(mbx, mby, &mb, &blocks);
                 self.intra_predict_chroma(mbx, mby, &mb, &blocks);
 
@@ -2110,7 +2284,7 @@ impl IntraMode {
     }
 }
 
-fn init_top_macroblocks(width: usize) -> Vec<MacroBlock> {
+fn init_top_macroblocks(top: &mut Vec<MacroBlock>, width: usize) {
     let mb_width = (width + 15) / 16;
 
     let mb = MacroBlock {
@@ -2120,7 +2294,13 @@ fn init_top_macroblocks(width: usize) -> Vec<MacroBlock> {
         ..MacroBlock::default()
     };
 
-    vec![mb; mb_width]
+    refill(top, mb_width, mb);
+}
+
+/// Sets `buf` to `len` copies of `value`, keeping its allocation.
+fn refill<T: Clone>(buf: &mut Vec<T>, len: usize, value: T) {
+    buf.clear();
+    buf.resize(len, value);
 }
 
 fn create_border_luma(mbx: usize, mby: usize, mbw: usize, top: &[u8], left: &[u8]) -> [u8; 357] {
@@ -2521,10 +2701,13 @@ mod test {
     extern crate test;
     use super::{
         add_residue, avg2, avg3, edge_pixels, predict_bhepred, predict_bldpred, predict_brdpred,
-        predict_bvepred, top_pixels,
+        predict_bvepred, top_pixels, MacroBlock, Vp8Decoder,
     };
     #[cfg(feature = "benchmarks")]
-    use super::{predict_4x4, IntraMode};
+    use super::{predict_4x4, Frame, IntraMode};
+    use crate::decoder::DecodingError;
+    use std::io::{Cursor, ErrorKind};
+    use std::mem;
     #[cfg(feature = "benchmarks")]
     use test::{black_box, Bencher};
 
@@ -2547,6 +2730,31 @@ mod test {
         v
     }
 
+    #[cfg(feature = "benchmarks")]
+    #[bench]
+    fn bench_fill_rgb_4k(b: &mut Bencher) {
+        let (width, height) = (3840, 2160);
+        let frame = Frame {
+            width,
+            height,
+            ybuf: (0..usize::from(width) * usize::from(height))
+                .map(|i| i as u8)
+                .collect(),
+            ubuf: (0..usize::from(width) * usize::from(height) / 4)
+                .map(|i| (i * 3) as u8)
+                .collect(),
+            vbuf: (0..usize::from(width) * usize::from(height) / 4)
+                .map(|i| (i * 7) as u8)
+                .collect(),
+            ..Default::default()
+        };
+        let mut buf = vec![0; frame.get_buf_size()];
+
+        b.iter(|| {
+            frame.fill_rgb(black_box(&mut buf));
+        });
+    }
+
     #[cfg(feature = "benchmarks")]
     #[bench]
     fn bench_predict_4x4(b: &mut Bencher) {
@@ -2865,4 +3073,53 @@ mod test {
         assert_eq!(im[39], avg_3);
         assert_eq!(im[40], avg_4);
     }
+
+    #[test]
+    fn memory_limit() {
+        let bytes = include_bytes!("../tests/images/1.webp");
+        let mut decoder = Vp8Decoder::new(Cursor::new(&bytes[20..]));
+        // The planes of the 550x368 image alone take about 300 KB.
+        decoder.set_memory_limit(200_000);
+        assert!(matches!(
+            decoder.decode_frame(),
+            Err(DecodingError::MemoryLimitExceeded)
+        ));
+
+        // Enough for the planes and the top row, but not for the state of all 35x23
+        // macroblocks.
+        let planes = 550 * 368 + 2 * 275 * 184;
+        let macroblock = mem::size_of::<MacroBlock>();
+        let mut decoder = Vp8Decoder::new(Cursor::new(&bytes[20..]));
+        decoder.set_memory_limit(planes + 35 * macroblock);
+        assert!(matches!(
+            decoder.decode_frame(),
+            Err(DecodingError::MemoryLimitExceeded)
+        ));
+
+        let mut decoder = Vp8Decoder::new(Cursor::new(&bytes[20..]));
+        decoder.set_memory_limit(planes + (35 * 23 + 35) * macroblock);
+        decoder.decode_frame().unwrap();
+    }
+
+    #[test]
+    fn truncated_partition() {
+        let bytes = include_bytes!("../tests/images/1.webp");
+        let data = &bytes[20..];
+        let decode = |len: usize, max_overread_bytes: Option<usize>| {
+            let mut decoder = Vp8Decoder::new(Cursor::new(&data[..len]));
+            if let Some(max_overread_bytes) = max_overread_bytes {
+                decoder.set_max_overread_bytes(max_overread_bytes);
+            }
+            decoder.decode_frame().map(|frame| frame.ybuf.clone())
+        };
+        let is_eof = |result: Result<_, DecodingError>| matches!(result, Err(DecodingError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof);
+
+        // Losing a few bytes only damages the last macroblocks.
+        let full = decode(data.len(), None).unwrap();
+        let truncated = decode(data.len() - 4, None).unwrap();
+        assert_eq!(full[..full.len() / 2], truncated[..full.len() / 2]);
+
+        assert!(is_eof(decode(data.len() - 4, Some(0))));
+        assert!(is_eof(decode(data.len() / 2, None)));
+    }
 }
@@ -0,0 +1,79 @@
+//! A vectorized version of the YUV to RGB conversion of `vp8::Frame`, enabled by the `simd`
+//! feature.
+//!
+//! It performs the same integer arithmetic as the scalar conversion on 8 pixels at once, so the
+//! output is bit exact.
+
+use wide::i32x8;
+
+const LANES: usize = 8;
+
+/// Converts as many whole groups of 8 pixels of a row as possible, writing the RGB values of each
+/// pixel to the first three of its `BPP` bytes in `out`. Returns the number of pixels converted;
+/// the rest of the row is left to the scalar conversion.
+pub(crate) fn fill_row<const BPP: usize>(
+    y_row: &[u8],
+    u_row: &[u8],
+    v_row: &[u8],
+    out: &mut [u8],
+) -> usize {
+    let len = y_row.len() / LANES * LANES;
+    for (x, pixels) in (0..len)
+        .step_by(LANES)
+        .zip(out.chunks_exact_mut(LANES * BPP))
+    {
+        let y = mulhi(load(|i| y_row[x + i]), 19077);
+        let u = load(|i| u_row[(x + i) / 2]);
+        let v = load(|i| v_row[(x + i) / 2]);
+
+        let r = clip(y + mulhi(v, 26149) - i32x8::splat(14234)).to_array();
+        let g = clip(y - mulhi(u, 6419) - mulhi(v, 13320) + i32x8::splat(8708)).to_array();
+        let b = clip(y + mulhi(u, 33050) - i32x8::splat(17685)).to_array();
+
+        for (i, pixel) in pixels.chunks_exact_mut(BPP).enumerate() {
+            pixel[0] = r[i] as u8;
+            pixel[1] = g[i] as u8;
+            pixel[2] = b[i] as u8;
+        }
+    }
+    len
+}
+
+fn load(f: impl Fn(usize) -> u8) -> i32x8 {
+    i32x8::new(std::array::from_fn(|i| i32::from(f(i))))
+}
+
+/// _mm_mulhi_epu16 emulation
+fn mulhi(v: i32x8, coeff: i32) -> i32x8 {
+    (v * i32x8::splat(coeff)) >> 8i32
+}
+
+/// Equivalent to the range check of the scalar `clip`, since out of range values shift to
+/// below 0 or above 255
+fn clip(v: i32x8) -> i32x8 {
+    let v: i32x8 = v >> 6i32;
+    v.max(i32x8::splat(0)).min(i32x8::splat(255))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vp8::Frame;
+
+    #[test]
+    fn matches_scalar_conversion() {
+        let y_row: Vec<u8> = (0..=255).collect();
+        let mut simd = vec![0; 256 * 4];
+        let mut scalar = vec![0; 256 * 4];
+        // Steps of 17 include both ends of the range, where the results are clipped.
+        for u in (0..=255).step_by(17) {
+            for v in (0..=255).step_by(17) {
+                let (u_row, v_row) = ([u; 128], [v; 128]);
+                assert_eq!(super::fill_row::<4>(&y_row, &u_row, &v_row, &mut simd), 256);
+                for (&y, pixel) in y_row.iter().zip(scalar.chunks_exact_mut(4)) {
+                    Frame::fill_single(y, u, v, pixel);
+                }
+                assert_eq!(simd, scalar, "u = {u}, v = {v}");
+            }
+        }
+    }
+}
@@ -62,6 +62,17 @@ fn refence_test(file: &str) {
             100 * num_bytes_different / data.len() < 10,
             "More than 10% of pixels differ"
         );
+
+        // The remaining differences are rounding errors. Every fixture stays above 50 dB, while
+        // skipping the loop filter drops several of them to between 40 and 45 dB.
+        let mse = data
+            .iter()
+            .zip(reference_data.iter())
+            .map(|(&a, &b)| (f64::from(a) - f64::from(b)).powi(2))
+            .sum::<f64>()
+            / data.len() as f64;
+        let psnr = 10.0 * (255.0f64.powi(2) / mse).log10();
+        assert!(psnr > 45.0, "PSNR of {psnr:.1} dB is too low");
     }
 }
 
@@ -83,3 +94,20 @@ macro_rules! reftest {
 reftest!(1, 2, 3, 4, 5);
 reftest!(1_webp_ll, 2_webp_ll, 3_webp_ll, 4_webp_ll, 5_webp_ll);
 reftest!(1_webp_a, 2_webp_a, 3_webp_a, 4_webp_a, 5_webp_a);
+
+/// The lossy reference images above only match within a tolerance, so this pins the exact output
+/// for one of them. The YUV to RGB conversion only uses integer arithmetic, so the result must
+/// not depend on the platform.
+#[test]
+fn lossy_output_is_bit_exact() {
+    let contents = std::fs::read("tests/images/1.webp").unwrap();
+    let mut decoder = webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    let mut data = vec![0; decoder.output_buffer_size()];
+    decoder.read_image(&mut data).unwrap();
+
+    // 64-bit FNV-1a
+    let hash = data.iter().fold(0xcbf29ce484222325u64, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    assert_eq!(hash, 0xf28bd8ff43c3eb37);
+}